@@ -1,7 +1,12 @@
-use wayland_client::{protocol::{wl_registry, wl_compositor, wl_subcompositor, wl_shm, wl_seat, wl_keyboard, wl_pointer, wl_output, wl_surface, wl_subsurface, wl_buffer, wl_shm_pool, wl_callback}, Connection, Dispatch, QueueHandle, WEnum};
+use wayland_client::{protocol::{wl_registry, wl_compositor, wl_subcompositor, wl_shm, wl_seat, wl_keyboard, wl_pointer, wl_touch, wl_output, wl_surface, wl_subsurface, wl_buffer, wl_shm_pool, wl_callback}, Connection, Dispatch, QueueHandle, WEnum};
 use wayland_protocols::ext::session_lock::v1::client::{ext_session_lock_manager_v1, ext_session_lock_v1, ext_session_lock_surface_v1};
+use wayland_protocols::wp::{fractional_scale::v1::client::{wp_fractional_scale_manager_v1, wp_fractional_scale_v1}, viewporter::client::{wp_viewporter, wp_viewport}};
+use calloop::signals::Signal;
+use xkbcommon::xkb;
 
-use crate::renderer;
+use std::{fs::File, io::Read, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use crate::{auth, config::{Config, EchoMode}, cursor::Cursor, fprint, hook, ipc, keys, logind::{self, Logind}, media, osk::Osk, password::Password, power, renderer::{self, LockRenderer, NewRenderer}, security_key::{self, Outcome}, session::Event, state::{Lifecycle, LockState, Unlock, UnlockReason}, text::Item, widget::{Action, Widget}};
 
 pub struct Surface {
     pub name: u32,
@@ -10,24 +15,138 @@ pub struct Surface {
     //pub child: Option<wl_surface::WlSurface>,
     //pub subsurface: Option<wl_subsurface::WlSubsurface>,
     pub lock_surface: Option<ext_session_lock_surface_v1::ExtSessionLockSurfaceV1>,
+    /// Whether the surface is on its output, assumed until the compositor says otherwise.
+    pub entered: bool,
+    /// Integer scale of the output.
+    pub scale: i32,
+    pub fractional_scale: Option<wp_fractional_scale_v1::WpFractionalScaleV1>,
+    pub viewport: Option<wp_viewport::WpViewport>,
+    /// Scale preferred by the compositor in 120ths, replaces `scale` once known.
+    pub preferred_scale: Option<u32>,
+    /// Logical size from the last configure.
+    pub size: (i32, i32),
+}
+
+impl Surface {
+    /// Physical pixels per logical one.
+    pub fn scale_factor(&self) -> f64 {
+        match self.preferred_scale {
+            Some(scale) => scale as f64 / 120.,
+            None => self.scale as f64,
+        }
+    }
+
+    /// Size of the buffer in physical pixels.
+    pub fn buffer_size(&self) -> (i32, i32) {
+        let scale = self.scale_factor();
+        // rounded half away from zero, as fractional-scale-v1 asks
+        ((self.size.0 as f64 * scale).round() as i32, (self.size.1 as f64 * scale).round() as i32)
+    }
+
+    /// Tell the compositor how the buffer maps onto the surface, applied with the next commit.
+    fn apply_scale(&self) {
+        let surface = match &self.surface {
+            Some(s) => s,
+            None => return,
+        };
+        match &self.viewport {
+            Some(viewport) if self.size.0 > 0 && self.size.1 > 0 => viewport.set_destination(self.size.0, self.size.1),
+            Some(_) => {}
+            None => surface.set_buffer_scale(self.scale),
+        }
+    }
 }
 
 pub struct AppData {
-    pub locked: bool,
-    pub running: bool,
+    pub lifecycle: Lifecycle,
     pub compositor: Option<wl_compositor::WlCompositor>,
     pub seat: Option<wl_seat::WlSeat>,
     pub seat_ptr: Option<wl_pointer::WlPointer>,
     pub seat_kb: Option<wl_keyboard::WlKeyboard>,
+    pub seat_touch: Option<wl_touch::WlTouch>,
+    /// Show the password field and buttons even without a pointer or touch
+    /// seat, for `wlock render`.
+    pub show_controls: bool,
     //pub subcompositor: Option<wl_subcompositor::WlSubcompositor>,
     pub shm: Option<wl_shm::WlShm>,
     pub surfaces: Vec<Surface>,
     pub lock_mgr: Option<ext_session_lock_manager_v1::ExtSessionLockManagerV1>,
-    pub renderer: Option<renderer::Renderer>,
+    pub fractional_scale_mgr: Option<wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1>,
+    pub viewporter: Option<wp_viewporter::WpViewporter>,
+    pub renderer: Option<Box<dyn LockRenderer>>,
+    pub new_renderer: NewRenderer,
+    pub config: Config,
+    /// Checks submitted passwords, started by the lock session.
+    pub authenticator: Option<auth::Worker>,
+    pub on_event: Option<Box<dyn FnMut(Event)>>,
 
-    //pub xkb_context: Context,
-    //pub xkb_keymap: Option<Keymap>,
-    //pub xkb_state: Option<State>,
+    pub xkb_context: xkb::Context,
+    pub xkb_keymap: Option<xkb::Keymap>,
+    pub xkb_state: Option<xkb::State>,
+    /// Depressed, latched and locked modifiers and the layout, as last sent by the compositor.
+    pub modifiers: [u32; 4],
+    /// Layouts switched on from the lock screen, on top of the compositor's.
+    pub layout_offset: u32,
+
+    pub password: Password,
+    /// The password is shown after tapping Show, in the `reveal` echo mode.
+    pub revealed: bool,
+    /// Who the session belongs to, while shown.
+    pub user_label: Option<String>,
+    pub help_visible: bool,
+    pub failed_attempts: u32,
+    /// Attempts are ignored until this point after a failure.
+    pub next_attempt: Option<Instant>,
+    /// Any input before this point unlocks without a password.
+    pub grace_until: Option<Instant>,
+    /// Set when locking because the system is about to sleep, disables the grace period.
+    pub sleep_lock: bool,
+    /// Owned by the lock while it runs, handed back to the daemon afterwards.
+    pub logind: Option<Logind>,
+    /// Shown above everything else, set through the control socket.
+    pub message: Option<String>,
+    /// Fingerprint verification running next to password entry.
+    pub fprint: Option<fprint::Handle>,
+    /// What the fingerprint reader wants the user to know.
+    pub fprint_status: Option<String>,
+    /// Security key checks running next to password entry.
+    pub security_key: Option<security_key::Handle>,
+    /// Prompt or error of the running security key check.
+    pub key_status: Option<String>,
+    /// `iTime`, only advanced while frames are drawn.
+    pub shader_time: Duration,
+    pub last_frame: Instant,
+    /// A frame callback is outstanding, the compositor stops sending them for hidden surfaces.
+    pub frame_requested: bool,
+    /// A frame is wanted, drawn once `next_frame` has passed.
+    pub frame_pending: bool,
+    pub next_frame: Instant,
+    /// Only draw when the overlay, the mouse or the clock changed.
+    pub static_mode: bool,
+    pub next_power_check: Instant,
+    /// Overlay, mouse and clock second of the last frame, to tell if anything changed.
+    pub drawn: Option<(Vec<Vec<Item>>, [f32; 4], u64)>,
+    /// Actions of the widgets in the last frame, in the same rows, so a
+    /// click runs what was on screen even if the widgets changed since.
+    pub drawn_actions: Vec<Vec<Option<Action>>>,
+
+    pub cursor: Option<Cursor>,
+    /// Serial of the last pointer enter, needed to change the cursor.
+    pub pointer_serial: u32,
+    pub cursor_visible: bool,
+    pub pointer_pos: (f64, f64),
+    /// Touch point that is treated like the pointer.
+    pub touch_id: Option<i32>,
+    /// Passed to shaders as `iMouse`.
+    pub mouse: [f32; 4],
+    pub osk: Osk,
+
+    /// Key repeat as requested by the compositor, in keys per second and ms.
+    pub repeat_rate: i32,
+    pub repeat_delay: i32,
+    /// Key being held down and when it repeats next.
+    pub repeat_key: Option<u32>,
+    pub next_repeat: Instant,
 }
 
 impl Dispatch<wl_registry::WlRegistry, ()> for AppData {
@@ -73,11 +192,27 @@ impl Dispatch<wl_registry::WlRegistry, ()> for AppData {
                         //child: None,
                         //subsurface: None,
                         lock_surface: None,
+                        entered: true,
+                        scale: 1,
+                        fractional_scale: None,
+                        viewport: None,
+                        preferred_scale: None,
+                        size: (0, 0),
                     });
-                    if state.running {
-                        conn.roundtrip().unwrap();
+                    if state.lifecycle.get() != LockState::Connecting {
+                        if let Err(e) = conn.roundtrip() {
+                            log::warn!("Unable to set up new output: {}", e);
+                        }
                     }
                 }
+                "wp_fractional_scale_manager_v1" => {
+                    let fractional_scale_mgr = registry.bind::<wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1, _, _>(name, version, qh, ());
+                    state.fractional_scale_mgr = Some(fractional_scale_mgr);
+                }
+                "wp_viewporter" => {
+                    let viewporter = registry.bind::<wp_viewporter::WpViewporter, _, _>(name, version, qh, ());
+                    state.viewporter = Some(viewporter);
+                }
                 "ext_session_lock_manager_v1" => {
                     let lock_mgr = registry.bind::<ext_session_lock_manager_v1::ExtSessionLockManagerV1, _, _>(name, version, qh, ());
                     state.lock_mgr = Some(lock_mgr);
@@ -95,6 +230,12 @@ impl Dispatch<wl_registry::WlRegistry, ()> for AppData {
                     if state.surfaces[i].lock_surface.is_some() {
                         state.surfaces[i].lock_surface.as_ref().unwrap().destroy();
                     }
+                    if let Some(fractional_scale) = &state.surfaces[i].fractional_scale {
+                        fractional_scale.destroy();
+                    }
+                    if let Some(viewport) = &state.surfaces[i].viewport {
+                        viewport.destroy();
+                    }
                     state.surfaces.remove(i);
                     break;
                 }
@@ -136,12 +277,20 @@ impl Dispatch<wl_seat::WlSeat, ()> for AppData {
             if state.seat_ptr.is_some() {
                 state.seat_ptr = None;
             }
+            if state.seat_touch.is_some() {
+                state.seat_touch = None;
+            }
             if capabilities.contains(wl_seat::Capability::Keyboard) {
                 state.seat_kb = Some(seat.get_keyboard(qh, ()));
             }
             if capabilities.contains(wl_seat::Capability::Pointer) {
                 state.seat_ptr = Some(seat.get_pointer(qh, ()));
             }
+            if capabilities.contains(wl_seat::Capability::Touch) {
+                state.seat_touch = Some(seat.get_touch(qh, ()));
+            }
+            // nothing to type with otherwise
+            state.osk.visible = state.seat_touch.is_some() && state.seat_kb.is_none();
         }
     }
 }
@@ -155,43 +304,164 @@ impl Dispatch<wl_keyboard::WlKeyboard, ()> for AppData {
         _: &Connection,
         _: &QueueHandle<Self>,
         ) {
-        if let wl_keyboard::Event::Key { key, .. } = event {
-            println!("Key: {}", key);
-            // todo use xkb keymap for correct code
-            if key == 1 {
-                state.locked = false;
-                state.running = false;
-            }
-        }
-
-        if let wl_keyboard::Event::Keymap { format, .. } = event {
-            if let WEnum::Value(wl_keyboard::KeymapFormat::XkbV1) = format {
-                //let keymap = Keymap::new_from_file(
-                //    &state.xkb_context,
-                //    &mut File::from(fd),
-                //    XKB_KEYMAP_FORMAT_TEXT_V1,
-                //    XKB_KEYMAP_COMPILE_NO_FLAGS);
-                //state.xkb_state = Some(State::new(&keymap.as_ref().unwrap()));
-                //state.xkb_keymap = keymap;
-            } else {
-                panic!("Unknown keymap format!");
+        match event {
+            wl_keyboard::Event::Keymap { format, fd, size } => {
+                // without a keymap only the pointer and touch work
+                if format != WEnum::Value(wl_keyboard::KeymapFormat::XkbV1) {
+                    log::error!("Unknown keymap format");
+                    return;
+                }
+                let mut keymap_str = String::with_capacity(size as usize);
+                if let Err(e) = File::from(fd).read_to_string(&mut keymap_str) {
+                    log::error!("Unable to read keymap: {}", e);
+                    return;
+                }
+                let keymap = match xkb::Keymap::new_from_string(
+                    &state.xkb_context,
+                    keymap_str.trim_end_matches('\0').to_string(),
+                    xkb::KEYMAP_FORMAT_TEXT_V1,
+                    xkb::KEYMAP_COMPILE_NO_FLAGS) {
+                    Some(keymap) => keymap,
+                    None => {
+                        log::error!("Unable to compile keymap");
+                        return;
+                    }
+                };
+                state.xkb_state = Some(xkb::State::new(&keymap));
+                state.xkb_keymap = Some(keymap);
+                state.layout_offset = 0;
+            }
+            wl_keyboard::Event::Modifiers { mods_depressed, mods_latched, mods_locked, group, .. } => {
+                state.set_modifiers([mods_depressed, mods_latched, mods_locked, group]);
             }
+            wl_keyboard::Event::Key { key, state: WEnum::Value(key_state), .. } => {
+                if key_state != wl_keyboard::KeyState::Pressed {
+                    if state.repeat_key == Some(key) {
+                        state.repeat_key = None;
+                    }
+                    return;
+                }
+                if state.xkb_state.is_none() {
+                    return;
+                }
+                if state.in_grace() {
+                    state.unlock(UnlockReason::Trusted);
+                    return;
+                }
+                let repeats = state.handle_key(key)
+                    // wayland keycodes are offset by 8 from xkb keycodes
+                    && state.xkb_keymap.as_ref().is_some_and(|k| k.key_repeats(key + 8));
+                if repeats && state.repeat_rate > 0 {
+                    state.repeat_key = Some(key);
+                    state.next_repeat = Instant::now() + Duration::from_millis(state.repeat_delay as u64);
+                } else {
+                    // a new press ends the repeat of any key held before
+                    state.repeat_key = None;
+                }
+            }
+            wl_keyboard::Event::RepeatInfo { rate, delay } => {
+                state.repeat_rate = rate;
+                state.repeat_delay = delay;
+                // a rate of zero turns repeat off, also for a key held right now
+                if rate <= 0 {
+                    state.repeat_key = None;
+                }
+            }
+            wl_keyboard::Event::Leave { .. } => state.repeat_key = None,
+            _ => {}
         }
     }
 }
 
+/// Linux input event code of the left mouse button.
+const BTN_LEFT: u32 = 0x110;
+
+/// Bullets shown in the `random` echo mode, at least the minimum and fewer than minimum plus spread.
+const RANDOM_ECHO_MIN: usize = 4;
+const RANDOM_ECHO_SPREAD: u64 = 9;
+
+/// Frames further apart than this do not advance `iTime`.
+const MAX_FRAME_GAP: Duration = Duration::from_secs(2);
+
+/// How often to look at the power supply for `battery-static`.
+const POWER_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
 impl Dispatch<wl_pointer::WlPointer, ()> for AppData {
     fn event(
-        _: &mut Self,
+        state: &mut Self,
         pointer: &wl_pointer::WlPointer,
         event: wl_pointer::Event,
         _: &(),
         _: &Connection,
+        qh: &QueueHandle<Self>,
+        ) {
+        match event {
+            wl_pointer::Event::Motion { .. } | wl_pointer::Event::Button { .. }
+                if state.config.grace_pointer && state.in_grace() => {
+                state.unlock(UnlockReason::Trusted);
+            }
+            wl_pointer::Event::Enter { serial, surface_x, surface_y, .. } => {
+                // hidden until the pointer is actually used
+                pointer.set_cursor(serial, None, 0, 0);
+                state.pointer_serial = serial;
+                state.cursor_visible = false;
+                state.pointer_pos = (surface_x, surface_y);
+            }
+            wl_pointer::Event::Motion { surface_x, surface_y, .. } => {
+                if !state.cursor_visible {
+                    if state.cursor.is_none() {
+                        state.cursor = Cursor::load(
+                            state.compositor.as_ref().unwrap(), state.shm.as_ref().unwrap(), qh);
+                    }
+                    if let Some(cursor) = &state.cursor {
+                        cursor.show(pointer, state.pointer_serial);
+                    }
+                    state.cursor_visible = true;
+                }
+                state.pointer_pos = (surface_x, surface_y);
+                state.pointer_move(surface_x, surface_y);
+            }
+            wl_pointer::Event::Button { button: BTN_LEFT, state: WEnum::Value(button_state), .. } => {
+                let (x, y) = state.pointer_pos;
+                match button_state {
+                    wl_pointer::ButtonState::Pressed => state.pointer_press(x, y),
+                    _ => state.pointer_release(),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_touch::WlTouch, ()> for AppData {
+    fn event(
+        state: &mut Self,
+        _: &wl_touch::WlTouch,
+        event: wl_touch::Event,
+        _: &(),
+        _: &Connection,
         _: &QueueHandle<Self>,
         ) {
-        if let wl_pointer::Event::Enter { serial, .. } = event {
-            // hide pointer
-            pointer.set_cursor(serial, None, 0, 0);
+        match event {
+            wl_touch::Event::Down { .. } if state.in_grace() => {
+                state.unlock(UnlockReason::Trusted);
+            }
+            wl_touch::Event::Down { id, x, y, .. } if state.touch_id.is_none() => {
+                state.touch_id = Some(id);
+                state.pointer_press(x, y);
+            }
+            wl_touch::Event::Motion { id, x, y, .. } if state.touch_id == Some(id) => {
+                state.pointer_move(x, y);
+            }
+            wl_touch::Event::Up { id, .. } if state.touch_id == Some(id) => {
+                state.touch_id = None;
+                state.pointer_release();
+            }
+            wl_touch::Event::Cancel => {
+                state.touch_id = None;
+                state.pointer_release();
+            }
+            _ => {}
         }
     }
 }
@@ -224,27 +494,104 @@ impl Dispatch<wl_shm::WlShm, ()> for AppData {
 
 impl Dispatch<wl_output::WlOutput, ()> for AppData {
     fn event(
-        _: &mut Self,
-        _: &wl_output::WlOutput,
-        _: wl_output::Event,
+        state: &mut Self,
+        output: &wl_output::WlOutput,
+        event: wl_output::Event,
         _: &(),
         _: &Connection,
         _: &QueueHandle<Self>,
         ) {
-        // todo
+        match event {
+            wl_output::Event::Scale { factor } => {
+                if let Some(s) = state.surfaces.iter_mut().find(|s| &s.output == output) {
+                    s.scale = factor.max(1);
+                }
+            }
+            // scale changes are atomic with the rest of the output state
+            wl_output::Event::Done => state.update_scale(),
+            _ => {}
+        }
     }
 }
 
 impl Dispatch<wl_surface::WlSurface, ()> for AppData {
+    fn event(
+        state: &mut Self,
+        surface: &wl_surface::WlSurface,
+        event: wl_surface::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        ) {
+        let was_visible = state.visible();
+        // the cursor surface is not one of ours
+        let s = match state.surfaces.iter_mut().find(|s| s.surface.as_ref() == Some(surface)) {
+            Some(s) => s,
+            None => return,
+        };
+        match event {
+            wl_surface::Event::Enter { .. } => s.entered = true,
+            wl_surface::Event::Leave { .. } => s.entered = false,
+            _ => {}
+        }
+        state.visibility_changed(was_visible);
+    }
+}
+
+impl Dispatch<wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1, ()> for AppData {
     fn event(
         _: &mut Self,
-        _: &wl_surface::WlSurface,
-        _: wl_surface::Event,
+        _: &wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        _: wp_fractional_scale_manager_v1::Event,
         _: &(),
         _: &Connection,
         _: &QueueHandle<Self>,
         ) {
-        // todo
+        // no event
+    }
+}
+
+impl Dispatch<wp_fractional_scale_v1::WpFractionalScaleV1, ()> for AppData {
+    fn event(
+        state: &mut Self,
+        fractional_scale: &wp_fractional_scale_v1::WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        ) {
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            if let Some(s) = state.surfaces.iter_mut().find(|s| s.fractional_scale.as_ref() == Some(fractional_scale)) {
+                s.preferred_scale = Some(scale);
+            }
+            state.update_scale();
+        }
+    }
+}
+
+impl Dispatch<wp_viewporter::WpViewporter, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &wp_viewporter::WpViewporter,
+        _: wp_viewporter::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        ) {
+        // no event
+    }
+}
+
+impl Dispatch<wp_viewport::WpViewport, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &wp_viewport::WpViewport,
+        _: wp_viewport::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        ) {
+        // no event
     }
 }
 
@@ -311,12 +658,21 @@ impl Dispatch<ext_session_lock_v1::ExtSessionLockV1, ()> for AppData {
         ) {
         match event {
             ext_session_lock_v1::Event::Finished => {
-                panic!("Unable to lock session!");
+                // ends the lock session with an error
+                state.transition(LockState::Failed);
             }
             ext_session_lock_v1::Event::Locked => {
-                state.locked = true;
-                println!("Session successfully locked!");
-
+                if !state.transition(LockState::Locked) {
+                    return;
+                }
+                if !state.config.grace.is_zero() && !state.sleep_lock {
+                    state.grace_until = Some(Instant::now() + state.config.grace);
+                }
+                if let Some(logind) = &mut state.logind {
+                    logind.locked();
+                }
+                log::info!("Session successfully locked!");
+                state.emit(Event::Locked);
             }
             _ => {}
         }
@@ -330,10 +686,14 @@ impl Dispatch<wl_callback::WlCallback, ()> for AppData {
         event: wl_callback::Event,
         _: &(),
         _: &Connection,
-        qh: &QueueHandle<AppData>,
+        _: &QueueHandle<AppData>,
         ) {
-        if let wl_callback::Event::Done { callback_data } = event {
-            state.render_and_schedule(qh, callback_data);
+        if let wl_callback::Event::Done { .. } = event {
+            state.frame_requested = false;
+            // drawn from the event loop, so the frame rate can be capped
+            if !state.static_mode {
+                state.frame_pending = true;
+            }
         }
     }
 }
@@ -349,31 +709,698 @@ impl Dispatch<ext_session_lock_surface_v1::ExtSessionLockSurfaceV1, ()> for AppD
         ) {
         if let ext_session_lock_surface_v1::Event::Configure { serial, width, height } = event {
             lock_surf.ack_configure(serial);
-            for s in &state.surfaces {
-                if let Some(surf) = &s.surface {
-                    if state.renderer.is_none() {
-                        let renderer = renderer::Renderer::new(&conn.display(), &surf, width as i32, height as i32);
-                        state.renderer = Some(renderer);
-                    } else {
-                        state.renderer.as_mut().unwrap().resize(width as i32, height as i32);
-                    }
+            if let Some(s) = state.surfaces.iter_mut().find(|s| s.lock_surface.as_ref() == Some(lock_surf)) {
+                s.size = (width as i32, height as i32);
+            }
+            if state.renderer.is_none() {
+                if let Some((surface, size)) = state.main_surface().map(|s| (s.surface.clone().unwrap(), s.buffer_size())) {
+                    state.renderer = Some((state.new_renderer)(&conn.display(), &surface, size, &state.config));
                 }
             }
+            state.update_scale();
             // this is required since first surface commit is used to enable lock surface
             // todo: maybe don't schedule this time since we're doing it anyway in main loop?
             // and this schedule doesn't seem to work anyway for some reason
-            state.render_and_schedule(qh, 0);
+            state.render_and_schedule(qh);
         }
     }
 }
 
 impl AppData {
-    pub fn render_and_schedule(&self, qh: &QueueHandle<AppData>, dt: u32) {
+    /// State before anything has been bound or locked.
+    pub fn new(config: Config, logind: Option<Logind>, sleep_lock: bool) -> Self {
+        AppData {
+            lifecycle: Lifecycle::new(),
+            compositor: None,
+            seat: None,
+            seat_ptr: None,
+            seat_kb: None,
+            seat_touch: None,
+            show_controls: false,
+            //subcompositor: None,
+            shm: None,
+            surfaces: vec![],
+            lock_mgr: None,
+            fractional_scale_mgr: None,
+            viewporter: None,
+            renderer: None,
+            new_renderer: Box::new(renderer::new_egl),
+            authenticator: None,
+            on_event: None,
+            config,
+
+            xkb_context: xkb::Context::new(xkb::CONTEXT_NO_FLAGS),
+            xkb_keymap: None,
+            xkb_state: None,
+            modifiers: [0; 4],
+            layout_offset: 0,
+
+            password: Password::default(),
+            revealed: false,
+            user_label: None,
+            help_visible: false,
+            failed_attempts: 0,
+            next_attempt: None,
+            grace_until: None,
+            sleep_lock,
+            logind,
+            message: None,
+            fprint: None,
+            fprint_status: None,
+            security_key: None,
+            key_status: None,
+            shader_time: Duration::ZERO,
+            last_frame: Instant::now(),
+            frame_requested: false,
+            frame_pending: false,
+            next_frame: Instant::now(),
+            static_mode: false,
+            next_power_check: Instant::now(),
+            drawn: None,
+            drawn_actions: vec![],
+
+            cursor: None,
+            pointer_serial: 0,
+            cursor_visible: false,
+            pointer_pos: (0., 0.),
+            touch_id: None,
+            mouse: [0.; 4],
+            osk: Osk::new(),
+
+            repeat_rate: 25,
+            repeat_delay: 600,
+            repeat_key: None,
+            next_repeat: Instant::now(),
+        }
+    }
+
+    pub fn render_and_schedule(&mut self, qh: &QueueHandle<AppData>) {
+        self.render();
         for s in &self.surfaces {
             if let Some(surf) = &s.surface {
-                self.renderer.as_ref().unwrap().render(dt);
                 surf.frame(qh, ());
             }
         }
+        self.frame_requested = true;
+    }
+
+    pub fn render(&mut self) {
+        let now = Instant::now();
+        let delta = now - self.last_frame;
+        // a longer gap means nothing was shown, continue where the animation stopped
+        if delta < MAX_FRAME_GAP {
+            self.shader_time += delta;
+        }
+        self.last_frame = now;
+        let dt = self.shader_time.as_millis() as u32;
+        let widgets = self.widgets();
+        let rows = self.items(&widgets);
+        self.drawn = Some((rows.clone(), self.mouse, clock_second()));
+        self.drawn_actions = widgets.iter()
+            .map(|row| row.iter().map(Widget::action).collect())
+            .collect();
+        self.next_frame = Instant::now() + self.config.frame_interval();
+        if let Some(renderer) = &mut self.renderer {
+            renderer.set_overlay(rows);
+            renderer.set_mouse(self.mouse);
+        }
+        if let Some(renderer) = &self.renderer {
+            for s in &self.surfaces {
+                if s.surface.is_some() {
+                    renderer.render(dt);
+                }
+            }
+        }
+    }
+
+    pub fn overlay_rows(&mut self) -> Vec<Vec<Item>> {
+        let widgets = self.widgets();
+        self.items(&widgets)
+    }
+
+    fn items(&self, widgets: &[Vec<Widget>]) -> Vec<Vec<Item>> {
+        widgets.iter()
+            .map(|row| row.iter().map(|w| self.widget_item(w)).collect())
+            .collect()
+    }
+
+    /// Whether the next frame would look different from the last one, ignoring animation.
+    fn content_changed(&mut self) -> bool {
+        let content = (self.overlay_rows(), self.mouse, clock_second());
+        self.drawn.as_ref() != Some(&content)
+    }
+
+    /// The surface the renderer draws on, once it has been configured.
+    fn main_surface(&self) -> Option<&Surface> {
+        self.surfaces.iter().find(|s| s.surface.is_some()).filter(|s| s.size.0 > 0 && s.size.1 > 0)
+    }
+
+    /// Physical pixels per logical one on the surface the renderer draws on.
+    fn scale_factor(&self) -> f64 {
+        self.main_surface().map_or(1., Surface::scale_factor)
     }
+
+    /// Apply the current scale of every surface and size the renderer in physical pixels.
+    fn update_scale(&mut self) {
+        for s in &self.surfaces {
+            s.apply_scale();
+        }
+        let (size, scale) = match self.main_surface() {
+            Some(s) => (s.buffer_size(), s.scale_factor()),
+            None => return,
+        };
+        if let Some(renderer) = &mut self.renderer {
+            if renderer.size() != size {
+                renderer.resize(size.0, size.1);
+            }
+            renderer.set_scale(scale as f32);
+        }
+        // the new scale only takes effect with the next commit
+        self.frame_pending = true;
+    }
+
+    /// Whether any surface can currently be seen. Outputs that are off need no
+    /// check of their own: the compositor stops answering frame callbacks for
+    /// them, which pauses drawing until they are back on.
+    fn visible(&self) -> bool {
+        self.surfaces.iter().any(|s| s.surface.is_some() && s.entered)
+    }
+
+    /// Restart drawing when a surface became visible again.
+    fn visibility_changed(&mut self, was_visible: bool) {
+        if self.visible() && !was_visible {
+            // callbacks requested while hidden may never be answered
+            self.frame_requested = false;
+            self.frame_pending = true;
+        }
+    }
+
+    /// Pick static or continuous rendering from the config and the power supply.
+    fn update_render_mode(&mut self) {
+        let now = Instant::now();
+        if now < self.next_power_check {
+            return;
+        }
+        self.next_power_check = now + POWER_CHECK_INTERVAL;
+
+        let static_mode = self.config.static_render || (self.config.battery_static && power::on_battery());
+        if static_mode != self.static_mode {
+            self.static_mode = static_mode;
+            // restarts frame callbacks when going back to continuous
+            self.frame_pending = true;
+        }
+    }
+
+    /// Answer a control socket request.
+    pub fn handle_request(&mut self, request: ipc::Request) -> String {
+        match request {
+            ipc::Request::Status => {
+                let state = self.lifecycle.get().name();
+                let message = match &self.message {
+                    Some(m) => format!("\"{}\"", ipc::json_escape(m)),
+                    None => "null".to_string(),
+                };
+                format!("{{\"state\":\"{}\",\"failed_attempts\":{},\"message\":{}}}",
+                        state, self.failed_attempts, message)
+            }
+            ipc::Request::Message(text) => {
+                self.message = Some(text).filter(|t| !t.is_empty());
+                "ok".to_string()
+            }
+            ipc::Request::Render => {
+                self.render();
+                "ok".to_string()
+            }
+            ipc::Request::Quit => {
+                log::info!("Unlock requested over the control socket");
+                self.unlock(UnlockReason::Trusted);
+                "ok".to_string()
+            }
+        }
+    }
+
+    pub fn handle_signal(&mut self, signal: Signal) {
+        match signal {
+            Signal::SIGUSR1 => {
+                log::info!("Unlock requested by SIGUSR1");
+                self.unlock(UnlockReason::Trusted);
+            }
+            Signal::SIGHUP => self.reload(),
+            s => log::info!("Ignoring {} while locked", s),
+        }
+    }
+
+    /// Read the config file and shader again, keeping the lock running.
+    /// Read the config again, keeping the current one if that fails. Never
+    /// looks at the command line of the process, which belongs to the host
+    /// program when wlock is embedded.
+    pub fn reload(&mut self) {
+        self.config = match self.config.reload() {
+            Some(Ok(config)) => config,
+            Some(Err(e)) => {
+                log::warn!("Keeping the old config: {}", e);
+                return;
+            }
+            None => {
+                log::info!("Config was not loaded from arguments, nothing to reload");
+                return;
+            }
+        };
+        log::info!("Reloaded config");
+        // pick up changes to the render mode right away
+        self.next_power_check = Instant::now();
+        if let Some(renderer) = &mut self.renderer {
+            renderer.set_shader(&self.config.shader_source());
+            renderer.set_render_scale(self.config.render_scale);
+        }
+    }
+
+    /// Handle everything logind sent while locked.
+    pub fn process_logind(&mut self) {
+        let events = match &mut self.logind {
+            Some(logind) => logind.process(),
+            None => return,
+        };
+        for event in events {
+            if let logind::Event::Unlock = event {
+                log::info!("Unlock requested by logind");
+                self.unlock(UnlockReason::Trusted);
+            }
+        }
+    }
+
+    /// Handle news from the fprintd thread.
+    pub fn handle_fprint(&mut self, event: fprint::Event) {
+        match event {
+            fprint::Event::Ready => {}
+            fprint::Event::Match => {
+                log::info!("Fingerprint matched");
+                self.unlock(UnlockReason::OtherFactor);
+            }
+            // an unknown finger is no guess at the password, so it neither
+            // counts as a failed attempt nor delays the next one
+            fprint::Event::NoMatch => self.fprint_status = Some("Fingerprint not recognized".to_string()),
+            fprint::Event::Retry(hint) => self.fprint_status = Some(hint.to_string()),
+            fprint::Event::Unavailable => {
+                self.fprint = None;
+                self.fprint_status = Some("Fingerprint reader unavailable".to_string());
+            }
+        }
+    }
+
+    /// Scan a finger again, after a failure or if the reader was unplugged.
+    fn restart_fprint(&mut self) {
+        match &self.fprint {
+            Some(fprint) => {
+                fprint.restart();
+                self.fprint_status = None;
+            }
+            None => self.fprint_status = Some("Fingerprint reader unavailable".to_string()),
+        }
+    }
+
+    /// Handle news from the security key check.
+    pub fn handle_security_key(&mut self, event: security_key::Event) {
+        let status = match event {
+            security_key::Event::Waiting => "Touch your security key",
+            security_key::Event::Done(Outcome::Touched) => {
+                log::info!("Security key touched");
+                self.unlock(UnlockReason::OtherFactor);
+                return;
+            }
+            security_key::Event::Done(Outcome::NoKey) => "No security key found",
+            security_key::Event::Done(Outcome::Timeout) => "Security key was not touched in time",
+        };
+        self.key_status = Some(status.to_string());
+    }
+
+    /// Overlay content for the current state, row by row.
+    fn widgets(&mut self) -> Vec<Vec<Widget>> {
+        let mut rows = vec![];
+        if let Some(message) = &self.message {
+            rows.push(vec![Widget::Label(message.clone())]);
+        }
+        if let Some(user) = &self.user_label {
+            rows.push(vec![Widget::Label(user.clone())]);
+        }
+        // only useful when there is something to click with
+        if self.show_controls || self.seat_ptr.is_some() || self.seat_touch.is_some() {
+            rows.push(vec![Widget::Password]);
+            if self.osk.visible {
+                rows.extend(self.osk.widgets(&self.xkb_context, self.xkb_state.as_ref()));
+            } else {
+                let mut buttons = vec![
+                    Widget::Button("Clear".to_string(), Action::Clear),
+                    Widget::Button("Unlock".to_string(), Action::Submit),
+                ];
+                if self.config.echo == EchoMode::Reveal {
+                    let label = if self.revealed { "Hide" } else { "Show" };
+                    buttons.push(Widget::Button(label.to_string(), Action::ToggleReveal));
+                }
+                rows.push(buttons);
+            }
+        }
+        if self.lifecycle.get() == LockState::Authenticating {
+            rows.push(vec![Widget::Label("Checking password".to_string())]);
+        }
+        let indicators = self.keyboard_indicators();
+        if !indicators.is_empty() {
+            rows.push(indicators);
+        }
+        match &self.fprint_status {
+            Some(status) => rows.push(vec![Widget::Label(status.clone())]),
+            None if self.fprint.is_some() => rows.push(vec![Widget::Label("Scan your finger to unlock".to_string())]),
+            None => {}
+        }
+        if let Some(status) = &self.key_status {
+            rows.push(vec![Widget::Label(status.clone())]);
+        }
+        match self.failed_attempts {
+            0 => {}
+            1 => rows.push(vec![Widget::Label("1 failed attempt".to_string())]),
+            n => rows.push(vec![Widget::Label(format!("{} failed attempts", n))]),
+        }
+        if let Some(next) = self.next_attempt {
+            let now = Instant::now();
+            if next > now {
+                let secs = (next - now).as_secs() + 1;
+                rows.push(vec![Widget::Label(format!("Try again in {}s", secs))]);
+            }
+        }
+        if self.help_visible {
+            rows.extend(self.config.bindings.iter()
+                .filter_map(|b| b.action.map(|action| format!("{}: {}", b, keys::action_name(action))))
+                .map(|line| vec![Widget::Label(line)]));
+        }
+        rows
+    }
+
+    fn widget_item(&self, widget: &Widget) -> Item {
+        match widget {
+            Widget::Label(text) => Item::Text(text.clone()),
+            Widget::Password if self.password.is_empty() => Item::Field("Password".to_string()),
+            Widget::Password => Item::Field(self.password_echo()),
+            Widget::Button(label, _) => Item::Button(label.clone()),
+        }
+    }
+
+    /// What the password field shows for a non-empty password.
+    pub fn password_echo(&self) -> String {
+        let bullets = match self.config.echo {
+            EchoMode::None => 0,
+            EchoMode::Bullets => self.password.len(),
+            EchoMode::Random => RANDOM_ECHO_MIN + (self.password.noise() % RANDOM_ECHO_SPREAD) as usize,
+            EchoMode::Reveal if self.reveal_active() => return self.revealed_password(),
+            EchoMode::Reveal => self.password.len(),
+        };
+        "\u{2022}".repeat(bullets)
+    }
+
+    /// The password with a bar where the cursor is.
+    fn revealed_password(&self) -> String {
+        let mut text: String = self.password.as_str().to_string();
+        let cursor = text.char_indices().nth(self.password.cursor()).map_or(text.len(), |(i, _)| i);
+        text.insert(cursor, '|');
+        text
+    }
+
+    fn reveal_active(&self) -> bool {
+        let alt = self.xkb_state.as_ref()
+            .is_some_and(|s| s.mod_name_is_active(xkb::MOD_NAME_ALT, xkb::STATE_MODS_EFFECTIVE));
+        self.revealed || alt
+    }
+
+    fn run_action(&mut self, action: Action) {
+        match action {
+            Action::Submit => self.submit_password(),
+            Action::Clear => self.password.clear(),
+            Action::Backspace => self.password.backspace(),
+            Action::DeleteWord => self.password.delete_word(),
+            Action::Delete => self.password.delete(),
+            Action::Left => self.password.left(),
+            Action::Right => self.password.right(),
+            Action::Home => self.password.home(),
+            Action::End => self.password.end(),
+            Action::Key(code) => {
+                let text = self.osk.key_text(&self.xkb_context, self.xkb_state.as_ref(), code);
+                self.password.insert(&text);
+                self.osk.shift = false;
+            }
+            Action::Shift => self.osk.shift = !self.osk.shift,
+            Action::ToggleKeyboard => self.osk.visible = !self.osk.visible,
+            Action::CycleLayout => self.cycle_layout(),
+            Action::ToggleReveal => self.revealed = !self.revealed,
+            Action::ToggleUser => {
+                self.user_label = match self.user_label {
+                    Some(_) => None,
+                    None => users::get_current_username()
+                        .map(|name| format!("Locked by {}", name.to_string_lossy())),
+                };
+            }
+            Action::ToggleHelp => self.help_visible = !self.help_visible,
+            Action::Fingerprint => self.restart_fprint(),
+            Action::SecurityKey => {
+                if let Some(key) = &self.security_key {
+                    key.retry();
+                }
+            }
+            Action::Media(key) => media::send(key),
+        }
+    }
+
+    /// Handle a key press, or a repeat of it. Returns whether holding the
+    /// key should repeat it, true for typing and editing.
+    fn handle_key(&mut self, key: u32) -> bool {
+        let (xkb_state, keymap) = match (&self.xkb_state, &self.xkb_keymap) {
+            (Some(s), Some(k)) => (s, k),
+            _ => return false,
+        };
+        // wayland keycodes are offset by 8 from xkb keycodes
+        let keycode = key + 8;
+        match keys::lookup_key(&self.config.bindings, keymap, xkb_state, keycode) {
+            Some(action) => {
+                self.run_action(action);
+                action.repeats()
+            }
+            None => {
+                let utf8 = xkb_state.key_get_utf8(keycode);
+                if !utf8.chars().any(char::is_control) {
+                    self.password.insert(&utf8);
+                }
+                true
+            }
+        }
+    }
+
+    /// Take on the modifiers and layout sent by the compositor.
+    pub fn set_modifiers(&mut self, modifiers: [u32; 4]) {
+        self.modifiers = modifiers;
+        self.update_modifiers();
+    }
+
+    /// Switch to the next layout, only for the lock screen. The compositor
+    /// can not be told, so its own switching keeps working on top of this.
+    pub fn cycle_layout(&mut self) {
+        self.layout_offset += 1;
+        self.update_modifiers();
+    }
+
+    fn update_modifiers(&mut self) {
+        let (xkb_state, keymap) = match (&mut self.xkb_state, &self.xkb_keymap) {
+            (Some(s), Some(k)) => (s, k),
+            _ => return,
+        };
+        let [depressed, latched, locked, group] = self.modifiers;
+        let layout = (group + self.layout_offset) % keymap.num_layouts().max(1);
+        xkb_state.update_mask(depressed, latched, locked, 0, 0, layout);
+    }
+
+    /// Caps Lock, Num Lock and the layout, the usual reasons for a mistyped password.
+    fn keyboard_indicators(&self) -> Vec<Widget> {
+        let (xkb_state, keymap) = match (&self.xkb_state, &self.xkb_keymap) {
+            (Some(s), Some(k)) => (s, k),
+            _ => return vec![],
+        };
+        let mut row = vec![];
+        if xkb_state.mod_name_is_active(xkb::MOD_NAME_CAPS, xkb::STATE_MODS_EFFECTIVE) {
+            row.push(Widget::Label("Caps Lock".to_string()));
+        }
+        if xkb_state.mod_name_is_active(xkb::MOD_NAME_NUM, xkb::STATE_MODS_EFFECTIVE) {
+            row.push(Widget::Label("Num Lock".to_string()));
+        }
+        let name = keymap.layout_get_name(xkb_state.serialize_layout(xkb::STATE_LAYOUT_EFFECTIVE));
+        if keymap.num_layouts() > 1 {
+            row.push(Widget::Button(name.to_string(), Action::CycleLayout));
+        } else if !name.is_empty() {
+            row.push(Widget::Label(name.to_string()));
+        }
+        row
+    }
+
+    /// When the event loop has to wake up next, if at all.
+    pub fn next_timeout(&self) -> Option<Instant> {
+        let frame = if !self.visible() {
+            None
+        } else if self.frame_pending {
+            Some(self.next_frame)
+        } else if self.static_mode && !self.frame_requested {
+            // the clock has to tick
+            let subsec = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+            Some(Instant::now() + Duration::from_nanos(1_000_000_000 - subsec as u64))
+        } else {
+            None
+        };
+        let repeat = self.repeat_key.map(|_| self.next_repeat);
+        frame.into_iter().chain(repeat).min()
+    }
+
+    /// Run everything that is due, called by the event loop after waking up.
+    pub fn handle_timers(&mut self, qh: &QueueHandle<AppData>) {
+        let now = Instant::now();
+        while let Some(key) = self.repeat_key {
+            if now < self.next_repeat {
+                break;
+            }
+            if !self.handle_key(key) {
+                self.repeat_key = None;
+                break;
+            }
+            self.next_repeat += Duration::from_secs(1) / self.repeat_rate as u32;
+        }
+
+        self.update_render_mode();
+        if self.static_mode && !self.frame_pending && !self.frame_requested {
+            self.frame_pending = self.content_changed();
+        }
+        if self.frame_pending && now >= self.next_frame && self.visible() {
+            self.frame_pending = false;
+            self.render_and_schedule(qh);
+        }
+    }
+
+    /// Pointer or touch pressed at surface position `x`, `y`, in logical coordinates.
+    fn pointer_press(&mut self, x: f64, y: f64) {
+        let scale = self.scale_factor();
+        let (x, y) = (x * scale, y * scale);
+        let height = self.renderer.as_ref().map_or(0, |r| r.size().1) as f64;
+        let (mx, my) = (x as f32, (height - y) as f32);
+        self.mouse = [mx, my, mx, my];
+
+        let hit = self.renderer.as_ref().and_then(|r| r.hit_test(x, y));
+        if let Some((row, col)) = hit {
+            let action = self.drawn_actions.get(row).and_then(|r| r.get(col)).copied().flatten();
+            if let Some(action) = action {
+                self.run_action(action);
+            }
+        }
+    }
+
+    fn pointer_move(&mut self, x: f64, y: f64) {
+        // only tracked while pressed, like shadertoy
+        if self.mouse[2] > 0. {
+            let scale = self.scale_factor();
+            let (x, y) = (x * scale, y * scale);
+            let height = self.renderer.as_ref().map_or(0, |r| r.size().1) as f64;
+            self.mouse[0] = x as f32;
+            self.mouse[1] = (height - y) as f32;
+        }
+    }
+
+    fn pointer_release(&mut self) {
+        self.mouse[2] = -self.mouse[2].abs();
+        self.mouse[3] = -self.mouse[3].abs();
+    }
+
+    fn in_grace(&self) -> bool {
+        self.grace_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        if let Some(on_event) = &mut self.on_event {
+            on_event(event);
+        }
+    }
+
+    /// Change the lock state, refusing and reporting illegal transitions.
+    pub fn transition(&mut self, next: LockState) -> bool {
+        match self.lifecycle.transition(next) {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Release the lock for `reason`, refusing and reporting it if the
+    /// current state does not allow that.
+    pub fn unlock(&mut self, reason: UnlockReason) -> bool {
+        match self.lifecycle.unlock(Unlock::new(reason)) {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("{}", e);
+                false
+            }
+        }
+    }
+
+    fn submit_password(&mut self) {
+        if self.password.is_empty() {
+            return;
+        }
+        if let Some(next) = self.next_attempt {
+            if Instant::now() < next {
+                return;
+            }
+        }
+
+        // the previous password is still being checked
+        if self.lifecycle.get() == LockState::Authenticating {
+            return;
+        }
+
+        if !self.transition(LockState::Authenticating) {
+            self.password.clear();
+            return;
+        }
+        let queued = self.authenticator.as_ref().is_some_and(|auth| auth.check(self.password.as_str()));
+        self.password.clear();
+        if !queued {
+            log::warn!("Password checking is not running");
+            self.transition(LockState::Locked);
+        }
+    }
+
+    /// Handle the result for the password submitted last.
+    pub fn password_checked(&mut self, success: bool) {
+        // unlocked some other way in the meantime
+        if self.lifecycle.get() != LockState::Authenticating {
+            return;
+        }
+        if success {
+            self.unlock(UnlockReason::Password);
+            return;
+        }
+        self.transition(LockState::Locked);
+        self.attempt_failed();
+    }
+
+    /// Count a rejected password or fingerprint and delay the next attempt.
+    fn attempt_failed(&mut self) {
+        self.failed_attempts += 1;
+        self.next_attempt = Some(Instant::now() + self.config.fail_delay_for(self.failed_attempts));
+        log::info!("Failed attempts: {}", self.failed_attempts);
+        self.emit(Event::AttemptFailed { attempts: self.failed_attempts });
+
+        if self.failed_attempts == self.config.fail_hook_after {
+            if let Some(hook) = &self.config.fail_hook {
+                hook::run(hook, &[("WLOCK_FAILED_ATTEMPTS", self.failed_attempts.to_string())]);
+            }
+        }
+    }
+}
+
+/// Seconds since the epoch, what `iDate` changes with.
+fn clock_second() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
 }
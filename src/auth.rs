@@ -0,0 +1,228 @@
+use std::{fs, io::Write, path::{Path, PathBuf}, process::{Command, Stdio}, sync::mpsc, thread};
+
+use calloop::channel::{self, Channel};
+
+/// Decides whether a password unlocks the session. Runs on its own thread,
+/// so taking a while like PAM does after a wrong password is fine.
+pub trait Authenticator: Send {
+    /// Check `password` for the user running wlock.
+    fn check(&mut self, password: &str) -> bool;
+
+    /// Called before the lock is requested. An error means no password could
+    /// ever pass `check`, so the session is not locked at all.
+    fn ready(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Passes passwords to an `Authenticator` on another thread.
+pub(crate) struct Worker {
+    passwords: mpsc::Sender<String>,
+}
+
+impl Worker {
+    /// Check passwords with `authenticator` on a new thread until the worker
+    /// is dropped. Results arrive on the returned channel in order.
+    pub fn spawn(mut authenticator: Box<dyn Authenticator>) -> (Self, Channel<bool>) {
+        let (passwords, receiver) = mpsc::channel::<String>();
+        let (sender, results) = channel::channel();
+        thread::spawn(move || {
+            for password in receiver {
+                if sender.send(authenticator.check(&password)).is_err() {
+                    return;
+                }
+            }
+        });
+        (Worker { passwords }, results)
+    }
+
+    /// Queue `password` for checking, `false` if the thread is gone.
+    pub fn check(&self, password: &str) -> bool {
+        self.passwords.send(password.to_string()).is_ok()
+    }
+}
+
+/// Checks passwords against a PAM service.
+pub struct Pam {
+    service: String,
+}
+
+impl Pam {
+    pub fn new(service: &str) -> Self {
+        Pam { service: service.to_string() }
+    }
+}
+
+impl Authenticator for Pam {
+    fn check(&mut self, password: &str) -> bool {
+        let username = match users::get_current_username() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => {
+                log::warn!("Unable to determine current user");
+                return false;
+            }
+        };
+
+        let mut authenticator = match pam::Authenticator::with_password(&self.service) {
+            Ok(a) => a,
+            Err(e) => {
+                log::warn!("Unable to start PAM: {}", e);
+                return false;
+            }
+        };
+        authenticator.get_handler().set_credentials(username, password);
+
+        match authenticator.authenticate() {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("Authentication failed: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Hash prefixes of the schemes `pwhash` can verify.
+const SHADOW_SCHEMES: &[&str] = &["$6$", "$5$", "$1$", "$2a$", "$2b$", "$2y$", "$sha1$"];
+
+/// Exit status of `wlock-chkpwd` for a wrong password, 0 means right.
+pub const CHKPWD_WRONG: i32 = 1;
+/// Exit status of `wlock-chkpwd` when no password could be right.
+pub const CHKPWD_UNUSABLE: i32 = 2;
+
+/// Checks passwords against the crypt hash of the current user in a
+/// `/etc/shadow` style file, for systems without PAM. Supports the SHA-512,
+/// SHA-256, SHA-1, MD5 and bcrypt schemes, not yescrypt.
+///
+/// wlock never reads the file itself. `/etc/shadow` is only readable by root
+/// and the `shadow` group, so every check runs `wlock-chkpwd`, a helper that
+/// does nothing but tell whether a password is right for the user running it,
+/// like PAM's `unix_chkpwd`. Only that helper is setgid `shadow`:
+///
+/// ```sh
+/// chgrp shadow /usr/bin/wlock-chkpwd && chmod g+s /usr/bin/wlock-chkpwd
+/// ```
+pub struct Shadow {
+    path: PathBuf,
+    helper: PathBuf,
+}
+
+impl Shadow {
+    pub fn new(path: &Path) -> Self {
+        Shadow { path: path.to_path_buf(), helper: PathBuf::from("wlock-chkpwd") }
+    }
+
+    /// Run `helper` instead of `wlock-chkpwd` from `PATH`.
+    pub fn helper(mut self, helper: &Path) -> Self {
+        self.helper = helper.to_path_buf();
+        self
+    }
+
+    /// Run the helper with `password` on stdin, or with `--ready` and nothing
+    /// to check. `Err` holds what the helper said about an unusable hash.
+    fn run_helper(&self, password: Option<&str>) -> Result<bool, String> {
+        let mut command = Command::new(&self.helper);
+        if password.is_none() {
+            command.arg("--ready");
+        }
+        let mut child = command.arg(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("unable to run {}: {}", self.helper.display(), e))?;
+        // closing stdin ends the password
+        let stdin = child.stdin.take();
+        if let (Some(mut stdin), Some(password)) = (stdin, password) {
+            let _ = stdin.write_all(password.as_bytes());
+        }
+        let output = child.wait_with_output()
+            .map_err(|e| format!("{} failed: {}", self.helper.display(), e))?;
+        match output.status.code() {
+            Some(0) => Ok(true),
+            Some(CHKPWD_WRONG) => Ok(false),
+            _ => {
+                let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+                if message.is_empty() {
+                    Err(format!("{} failed: {}", self.helper.display(), output.status))
+                } else {
+                    Err(message)
+                }
+            }
+        }
+    }
+}
+
+impl Authenticator for Shadow {
+    fn check(&mut self, password: &str) -> bool {
+        match self.run_helper(Some(password)) {
+            Ok(right) => right,
+            Err(e) => {
+                log::warn!("Unable to check password: {}", e);
+                false
+            }
+        }
+    }
+
+    fn ready(&self) -> Result<(), String> {
+        self.run_helper(None).map(drop)
+    }
+}
+
+/// The file `wlock-chkpwd` checks passwords against, see `Shadow`.
+pub struct ShadowFile {
+    path: PathBuf,
+}
+
+impl ShadowFile {
+    pub fn new(path: &Path) -> Self {
+        ShadowFile { path: path.to_path_buf() }
+    }
+
+    /// Hash of `user`, read on every check so password changes apply right away.
+    fn hash(&self, user: &str) -> Result<String, String> {
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|e| format!("unable to read {}: {}", self.path.display(), e))?;
+        contents.lines()
+            .map(|line| line.split(':'))
+            .find_map(|mut fields| match (fields.next(), fields.next()) {
+                (Some(name), Some(hash)) if name == user => Some(hash.to_string()),
+                _ => None,
+            })
+            .ok_or_else(|| format!("no entry for {} in {}", user, self.path.display()))
+    }
+
+    /// Hash of `user` if it can be checked at all. Locked accounts start with
+    /// '!' or '*' and an empty hash is no password, neither unlocks.
+    pub fn usable_hash(&self, user: &str) -> Result<String, String> {
+        let hash = self.hash(user)?;
+        if !SHADOW_SCHEMES.iter().any(|scheme| hash.starts_with(scheme)) {
+            return Err(format!("the password hash of {} is locked, empty or of an unsupported scheme like yescrypt", user));
+        }
+        Ok(hash)
+    }
+
+    pub fn verify(&self, user: &str, password: &str) -> Result<bool, String> {
+        self.usable_hash(user).map(|hash| pwhash::unix::verify(password, &hash))
+    }
+}
+
+/// Accepts exactly one password, for tests that go through the whole UI.
+#[cfg(any(test, feature = "stub-auth"))]
+pub struct Stub {
+    password: String,
+}
+
+#[cfg(any(test, feature = "stub-auth"))]
+impl Stub {
+    pub fn new(password: &str) -> Self {
+        Stub { password: password.to_string() }
+    }
+}
+
+#[cfg(any(test, feature = "stub-auth"))]
+impl Authenticator for Stub {
+    fn check(&mut self, password: &str) -> bool {
+        password == self.password
+    }
+}
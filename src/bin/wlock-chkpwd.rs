@@ -0,0 +1,63 @@
+//! `wlock-chkpwd [--ready] [SHADOW_FILE]`: check the password on stdin against
+//! the shadow hash of the user running it, for `auth::Shadow`.
+//!
+//! This is the only part of wlock that may be setgid `shadow`, so it reads
+//! nothing but the shadow file and says nothing but the exit status and why a
+//! hash is unusable. While privileged it only reads `/etc/shadow`.
+
+use std::{
+    env,
+    io::{self, Read},
+    path::PathBuf,
+    process,
+};
+
+use wlock::auth::{ShadowFile, CHKPWD_UNUSABLE, CHKPWD_WRONG};
+
+const SHADOW: &str = "/etc/shadow";
+/// Longer passwords are cut off, no hash scheme looks further anyway.
+const MAX_PASSWORD: u64 = 4096;
+
+fn main() {
+    let mut ready = false;
+    let mut path = PathBuf::from(SHADOW);
+    for arg in env::args().skip(1) {
+        match &arg[..] {
+            "--ready" => ready = true,
+            _ => path = PathBuf::from(arg),
+        }
+    }
+
+    let privileged = unsafe { libc::getegid() != libc::getgid() || libc::geteuid() != libc::getuid() };
+    if privileged && path != PathBuf::from(SHADOW) {
+        fail(&format!("only {} can be read while setgid", SHADOW));
+    }
+    // the real user, not whoever the helper runs as
+    let user = match users::get_current_username() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => fail("unable to determine current user"),
+    };
+
+    let file = ShadowFile::new(&path);
+    if ready {
+        if let Err(e) = file.usable_hash(&user) {
+            fail(&e);
+        }
+        return;
+    }
+
+    let mut password = String::new();
+    if io::stdin().take(MAX_PASSWORD).read_to_string(&mut password).is_err() {
+        process::exit(CHKPWD_WRONG);
+    }
+    match file.verify(&user, &password) {
+        Ok(true) => {}
+        Ok(false) => process::exit(CHKPWD_WRONG),
+        Err(e) => fail(&e),
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(CHKPWD_UNUSABLE);
+}
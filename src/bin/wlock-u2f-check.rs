@@ -0,0 +1,23 @@
+//! `wlock-u2f-check SERVICE`: wait for a touch on a security key through the
+//! PAM service `SERVICE`, for `security_key::PamU2f`. Exits with
+//! `Outcome::exit_code`.
+//!
+//! PAM runs here rather than in a forked copy of wlock, where locks held by
+//! its other threads could never be released.
+
+use std::{env, process};
+
+use wlock::security_key::{Outcome, PamU2f};
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let service = match env::args().nth(1) {
+        Some(service) => service,
+        None => {
+            eprintln!("usage: wlock-u2f-check SERVICE");
+            process::exit(Outcome::NoKey.exit_code());
+        }
+    };
+    process::exit(PamU2f::new(&service).authenticate().exit_code());
+}
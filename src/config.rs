@@ -0,0 +1,317 @@
+use std::{env, fs, path::{Path, PathBuf}, time::Duration};
+
+use crate::{auth::{self, Authenticator}, keys::{self, Binding}, renderer};
+
+/// Where passwords are checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthBackend {
+    Pam,
+    /// Crypt hashes from `shadow_file`, for systems without PAM. Checked by
+    /// the setgid `shadow_helper`, see `auth::Shadow`.
+    Shadow,
+    /// Accepts `stub_password` only, for testing the UI without real credentials.
+    #[cfg(any(test, feature = "stub-auth"))]
+    Stub,
+}
+
+/// How the password field shows what was typed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EchoMode {
+    /// An empty field, not even the length shows.
+    None,
+    /// A bullet per character.
+    Bullets,
+    /// A number of bullets that changes randomly with every edit.
+    Random,
+    /// Bullets, replaced by the password while Alt is held or after tapping Show.
+    Reveal,
+}
+
+/// Runtime options, read from `$XDG_CONFIG_HOME/wlock/config` and then
+/// overridden by `--key=value` command line arguments.
+///
+/// The config file uses the same keys as the command line, one `key=value`
+/// per line. Empty lines and lines starting with `#` are ignored.
+#[derive(Clone)]
+pub struct Config {
+    pub auth: AuthBackend,
+    /// PAM service used to check the password.
+    pub pam_service: String,
+    /// File with the password hashes for the shadow backend.
+    pub shadow_file: PathBuf,
+    /// `wlock-chkpwd`, the only part of wlock that may read `shadow_file`.
+    pub shadow_helper: PathBuf,
+    #[cfg(any(test, feature = "stub-auth"))]
+    pub stub_password: String,
+    /// Also accept a fingerprint through fprintd while the password can be typed.
+    pub fingerprint: bool,
+    /// Also accept a touch on a security key, checked with `u2f_service`.
+    pub security_key: bool,
+    /// PAM service set up with pam_u2f and its `cue` option.
+    pub u2f_service: String,
+    /// `wlock-u2f-check`, which runs the `u2f_service` check in its own process.
+    pub u2f_helper: PathBuf,
+    /// Delay after the first failed attempt, doubled on every further failure.
+    pub fail_delay: Duration,
+    /// Upper bound for the delay between attempts.
+    pub fail_delay_max: Duration,
+    /// Shell command run once `fail_hook_after` attempts have failed.
+    pub fail_hook: Option<String>,
+    pub fail_hook_after: u32,
+    /// Time after locking during which any input unlocks without a password.
+    pub grace: Duration,
+    /// Whether pointer motion also ends the lock during the grace period.
+    pub grace_pointer: bool,
+    /// Idle time after which the daemon locks the session, zero disables it.
+    pub idle_lock: Duration,
+    /// Idle time after which the daemon runs `idle_dim_command`, zero disables it.
+    pub idle_dim: Duration,
+    pub idle_dim_command: Option<String>,
+    /// Run when the user becomes active again after the dim stage.
+    pub idle_resume_command: Option<String>,
+    /// Let the daemon follow logind lock requests and lock before sleep.
+    pub logind: bool,
+    /// Control socket, off unless set. Anything running as the user can
+    /// unlock through it, like with SIGUSR1.
+    pub socket: Option<PathBuf>,
+    /// File with a Shadertoy style `mainImage` function drawn as background.
+    pub shader: Option<PathBuf>,
+    /// Upper bound for the frame rate, zero means as fast as the compositor asks.
+    pub fps: u32,
+    /// Draw once and then only when something visible changes.
+    pub static_render: bool,
+    /// Switch to static rendering while running on battery.
+    pub battery_static: bool,
+    /// Fraction of the surface size the shader is drawn at before being upscaled.
+    pub render_scale: f32,
+    pub echo: EchoMode,
+    /// Keys that do something other than typing, set with `bind=Ctrl+u clear`.
+    pub bindings: Vec<Binding>,
+    /// Arguments given to `load_args`, applied again when SIGHUP reloads the
+    /// config. `None` for a config built in code, which a reload keeps as is.
+    pub args: Option<Vec<String>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            auth: AuthBackend::Pam,
+            pam_service: "login".to_string(),
+            shadow_file: PathBuf::from("/etc/shadow"),
+            shadow_helper: PathBuf::from("wlock-chkpwd"),
+            #[cfg(any(test, feature = "stub-auth"))]
+            stub_password: "wlock".to_string(),
+            fingerprint: false,
+            security_key: false,
+            u2f_service: "wlock-u2f".to_string(),
+            u2f_helper: PathBuf::from("wlock-u2f-check"),
+            fail_delay: Duration::from_secs(1),
+            fail_delay_max: Duration::from_secs(60),
+            fail_hook: None,
+            fail_hook_after: 3,
+            grace: Duration::ZERO,
+            grace_pointer: false,
+            idle_lock: Duration::from_secs(300),
+            idle_dim: Duration::ZERO,
+            idle_dim_command: None,
+            idle_resume_command: None,
+            logind: false,
+            socket: None,
+            shader: None,
+            fps: 0,
+            static_render: false,
+            battery_static: false,
+            render_scale: 1.,
+            echo: EchoMode::Bullets,
+            bindings: keys::defaults(),
+            args: None,
+        }
+    }
+}
+
+impl Config {
+    /// Errors in the config file are only logged, the options there are not
+    /// under the control of whoever runs wlock right now. A bad command line
+    /// argument is an error.
+    pub fn load() -> Result<Self, String> {
+        Self::load_args(env::args().skip(1))
+    }
+
+    /// Like `load`, with `args` in place of the command line.
+    pub fn load_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        Self::load_with(config_path().as_deref(), args)
+    }
+
+    /// Like `load_args`, reading the config file at `file` instead of the
+    /// user's, or none at all.
+    pub fn load_with(file: Option<&Path>, args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let args: Vec<String> = args.into_iter().collect();
+        let mut config = Config { args: Some(args.clone()), ..Config::default() };
+
+        if let Some(path) = file {
+            if let Ok(contents) = fs::read_to_string(path) {
+                for (i, line) in contents.lines().enumerate() {
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    let (key, value) = line.split_once('=').unwrap_or((line, ""));
+                    if let Err(e) = config.set(key.trim(), value.trim()) {
+                        log::warn!("{}:{}: {}", path.display(), i + 1, e);
+                    }
+                }
+            }
+        }
+
+        for arg in &args {
+            if let Some(arg) = arg.strip_prefix("--") {
+                let (key, value) = arg.split_once('=').unwrap_or((arg, ""));
+                config.set(key, value)?;
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "auth" => self.auth = parse_auth(key, value)?,
+            "pam-service" => self.pam_service = value.to_string(),
+            "shadow-file" => self.shadow_file = PathBuf::from(value),
+            "shadow-helper" => self.shadow_helper = PathBuf::from(value),
+            #[cfg(any(test, feature = "stub-auth"))]
+            "stub-password" => self.stub_password = value.to_string(),
+            "fingerprint" => self.fingerprint = parse_bool(key, value)?,
+            "security-key" => self.security_key = parse_bool(key, value)?,
+            "u2f-service" => self.u2f_service = value.to_string(),
+            "u2f-helper" => self.u2f_helper = PathBuf::from(value),
+            "fail-delay" => self.fail_delay = parse_secs(key, value)?,
+            "fail-delay-max" => self.fail_delay_max = parse_secs(key, value)?,
+            "fail-hook" => self.fail_hook = parse_command(value),
+            "fail-hook-after" => self.fail_hook_after = parse(key, value)?,
+            "grace" => self.grace = parse_secs(key, value)?,
+            "grace-pointer" => self.grace_pointer = parse_bool(key, value)?,
+            "idle-lock" => self.idle_lock = parse_secs(key, value)?,
+            "idle-dim" => self.idle_dim = parse_secs(key, value)?,
+            "idle-dim-command" => self.idle_dim_command = parse_command(value),
+            "idle-resume-command" => self.idle_resume_command = parse_command(value),
+            "logind" => self.logind = parse_bool(key, value)?,
+            "socket" => self.socket = Some(PathBuf::from(value)).filter(|p| !p.as_os_str().is_empty()),
+            "shader" => self.shader = Some(PathBuf::from(value)).filter(|p| !p.as_os_str().is_empty()),
+            "fps" => self.fps = parse(key, value)?,
+            "static" => self.static_render = parse_bool(key, value)?,
+            "battery-static" => self.battery_static = parse_bool(key, value)?,
+            "render-scale" => self.render_scale = parse_fraction(key, value)?,
+            "echo" => self.echo = parse_echo(key, value)?,
+            "bind" => keys::add(&mut self.bindings, Binding::parse(value)?),
+            _ => return Err(format!("unknown option '{}'", key)),
+        }
+        Ok(())
+    }
+
+    /// The configured password check.
+    pub fn authenticator(&self) -> Box<dyn Authenticator> {
+        match self.auth {
+            AuthBackend::Pam => Box::new(auth::Pam::new(&self.pam_service)),
+            AuthBackend::Shadow => Box::new(auth::Shadow::new(&self.shadow_file).helper(&self.shadow_helper)),
+            #[cfg(any(test, feature = "stub-auth"))]
+            AuthBackend::Stub => Box::new(auth::Stub::new(&self.stub_password)),
+        }
+    }
+
+    /// Source of the background shader, the built-in one if unset or unreadable.
+    pub fn shader_source(&self) -> String {
+        let path = match &self.shader {
+            Some(path) => path,
+            None => return renderer::DEFAULT_SHADER.to_string(),
+        };
+        fs::read_to_string(path).unwrap_or_else(|e| {
+            log::warn!("Unable to read shader {}: {}", path.display(), e);
+            renderer::DEFAULT_SHADER.to_string()
+        })
+    }
+
+    /// The config as `args` and the config file say now, `None` if it was
+    /// not loaded from them.
+    pub fn reload(&self) -> Option<Result<Config, String>> {
+        self.args.clone().map(Self::load_args)
+    }
+
+    /// Shortest time between two frames.
+    pub fn frame_interval(&self) -> Duration {
+        match self.fps {
+            0 => Duration::ZERO,
+            fps => Duration::from_secs(1) / fps,
+        }
+    }
+
+    /// Time to wait before the next attempt is accepted after `failed` failures.
+    pub fn fail_delay_for(&self, failed: u32) -> Duration {
+        if failed == 0 {
+            return Duration::ZERO;
+        }
+        let factor = 1u32.checked_shl(failed - 1).unwrap_or(u32::MAX);
+        self.fail_delay.saturating_mul(factor).min(self.fail_delay_max)
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("wlock").join("config"))
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value '{}' for '{}'", value, key))
+}
+
+fn parse_command(value: &str) -> Option<String> {
+    Some(value.to_string()).filter(|v| !v.is_empty())
+}
+
+/// A bare key, as in `--grace-pointer`, means `true`.
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "" | "true" | "yes" | "1" => Ok(true),
+        "false" | "no" | "0" => Ok(false),
+        _ => Err(format!("invalid value '{}' for '{}'", value, key)),
+    }
+}
+
+fn parse_auth(key: &str, value: &str) -> Result<AuthBackend, String> {
+    match value {
+        "pam" => Ok(AuthBackend::Pam),
+        "shadow" => Ok(AuthBackend::Shadow),
+        #[cfg(any(test, feature = "stub-auth"))]
+        "stub" => Ok(AuthBackend::Stub),
+        _ => Err(format!("invalid value '{}' for '{}'", value, key)),
+    }
+}
+
+fn parse_echo(key: &str, value: &str) -> Result<EchoMode, String> {
+    match value {
+        "none" => Ok(EchoMode::None),
+        "bullets" => Ok(EchoMode::Bullets),
+        "random" => Ok(EchoMode::Random),
+        "reveal" => Ok(EchoMode::Reveal),
+        _ => Err(format!("invalid value '{}' for '{}'", value, key)),
+    }
+}
+
+fn parse_fraction(key: &str, value: &str) -> Result<f32, String> {
+    let fraction: f32 = parse(key, value)?;
+    if !(fraction > 0. && fraction <= 1.) {
+        return Err(format!("'{}' must be greater than 0 and at most 1", key));
+    }
+    Ok(fraction)
+}
+
+fn parse_secs(key: &str, value: &str) -> Result<Duration, String> {
+    let secs: f32 = parse(key, value)?;
+    if secs < 0. {
+        return Err(format!("'{}' must not be negative", key));
+    }
+    // also catches nan, inf and values too large for a Duration
+    Duration::try_from_secs_f32(secs).map_err(|_| format!("invalid value '{}' for '{}'", value, key))
+}
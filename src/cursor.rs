@@ -0,0 +1,55 @@
+use std::{env, fs, io::Write, os::unix::io::AsRawFd};
+
+use wayland_client::{protocol::{wl_buffer, wl_compositor, wl_pointer, wl_shm, wl_surface}, QueueHandle};
+
+use crate::app_data::AppData;
+
+/// Pointer image from the user's xcursor theme.
+pub struct Cursor {
+    surface: wl_surface::WlSurface,
+    _buffer: wl_buffer::WlBuffer,
+    hotspot: (i32, i32),
+}
+
+impl Cursor {
+    /// Load the default pointer of `$XCURSOR_THEME` at `$XCURSOR_SIZE`.
+    pub fn load(compositor: &wl_compositor::WlCompositor, shm: &wl_shm::WlShm, qh: &QueueHandle<AppData>) -> Option<Self> {
+        let theme = env::var("XCURSOR_THEME").unwrap_or_else(|_| "default".to_string());
+        let size = env::var("XCURSOR_SIZE").ok().and_then(|s| s.parse().ok()).unwrap_or(24);
+
+        let theme = xcursor::CursorTheme::load(&theme);
+        let path = theme.load_icon("left_ptr").or_else(|| theme.load_icon("default"))?;
+        let images = xcursor::parser::parse_xcursor(&fs::read(path).ok()?)?;
+        let image = images.iter().min_by_key(|i| (i.size as i32 - size).abs())?;
+
+        let mut file = tempfile::tempfile().ok()?;
+        file.write_all(argb8888(image)).ok()?;
+
+        let (width, height) = (image.width as i32, image.height as i32);
+        let pool = shm.create_pool(file.as_raw_fd(), width * height * 4, qh, ());
+        let buffer = pool.create_buffer(0, width, height, width * 4, wl_shm::Format::Argb8888, qh, ());
+        pool.destroy();
+
+        let surface = compositor.create_surface(qh, ());
+        surface.attach(Some(&buffer), 0, 0);
+        surface.damage(0, 0, width, height);
+        surface.commit();
+
+        Some(Cursor {
+            surface,
+            _buffer: buffer,
+            hotspot: (image.xhot as i32, image.yhot as i32),
+        })
+    }
+
+    pub fn show(&self, pointer: &wl_pointer::WlPointer, serial: u32) {
+        pointer.set_cursor(serial, Some(&self.surface), self.hotspot.0, self.hotspot.1);
+    }
+}
+
+/// Pixels of `image` laid out for `wl_shm::Format::Argb8888`. xcursor files
+/// store little endian ARGB words, which `pixels_rgba` keeps as they are,
+/// despite its name. That is the byte order wl_shm expects.
+pub(crate) fn argb8888(image: &xcursor::parser::Image) -> &[u8] {
+    &image.pixels_rgba
+}
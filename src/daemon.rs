@@ -0,0 +1,249 @@
+use calloop::{generic::Generic, EventLoop, Interest, Mode, PostAction};
+use wayland_client::{protocol::{wl_registry, wl_seat}, Connection, Dispatch, QueueHandle, WaylandSource};
+use wayland_protocols::ext::idle_notify::v1::client::{ext_idle_notifier_v1, ext_idle_notification_v1};
+
+use crate::{config::Config, hook, logind::{self, Logind}, session::{Error, LockSession}};
+
+/// Which timeout an idle notification belongs to.
+#[derive(Clone, Copy)]
+enum Stage {
+    Dim,
+    Lock,
+}
+
+struct Daemon {
+    seat: Option<wl_seat::WlSeat>,
+    notifier: Option<ext_idle_notifier_v1::ExtIdleNotifierV1>,
+    notifications: Vec<ext_idle_notification_v1::ExtIdleNotificationV1>,
+    dimmed: bool,
+    lock_requested: bool,
+    sleep_lock: bool,
+    logind: Option<Logind>,
+    config: Config,
+}
+
+/// Wait for the seat to go idle, or for logind to ask for it, and lock the
+/// session, until the connection to the compositor is lost. A lock that
+/// fails is logged and the daemon waits for the next one.
+pub fn run(conn: &Connection, config: &Config) -> Result<(), Error> {
+    let logind = config.logind.then(Logind::connect).transpose()?;
+    run_with(conn, config, logind, |session| session)
+}
+
+/// Like `run`, following `logind` instead of connecting to the system bus,
+/// with every lock set up further by `setup`.
+pub(crate) fn run_with(conn: &Connection, config: &Config, mut logind: Option<Logind>, setup: impl Fn(LockSession) -> LockSession) -> Result<(), Error> {
+    let display = conn.display();
+
+    let mut event_queue = conn.new_event_queue();
+    let qh = event_queue.handle();
+
+    let _registry = display.get_registry(&qh, ());
+
+    let mut daemon = Daemon {
+        seat: None,
+        notifier: None,
+        notifications: vec![],
+        dimmed: false,
+        lock_requested: false,
+        sleep_lock: false,
+        logind: None,
+        config: config.clone(),
+    };
+    event_queue.roundtrip(&mut daemon)?;
+
+    if daemon.seat.is_none() {
+        return Err(Error::MissingGlobal("wl_seat"));
+    }
+    if daemon.notifier.is_none() && !config.idle_lock.is_zero() {
+        return Err(Error::MissingGlobal("ext_idle_notifier_v1"));
+    }
+
+    if let Some(logind) = &mut logind {
+        logind.inhibit();
+    }
+    daemon.logind = logind;
+
+    daemon.arm(&qh);
+
+    let mut event_loop: EventLoop<Daemon> = EventLoop::try_new()?;
+    let handle = event_loop.handle();
+
+    WaylandSource::new(event_queue)?.insert(handle.clone())?;
+
+    if let Some(fd) = daemon.logind.as_ref().map(Logind::fd) {
+        handle.insert_source(Generic::new(fd, Interest::READ, Mode::Level), |_, _, daemon| {
+            daemon.process_logind();
+            Ok(PostAction::Continue)
+        })?;
+    }
+
+    loop {
+        event_loop.dispatch(None, &mut daemon)?;
+
+        if daemon.lock_requested {
+            daemon.disarm();
+            let (logind, result) = setup(LockSession::new(conn, config.clone()))
+                .logind(daemon.logind.take(), daemon.sleep_lock)
+                .run();
+            daemon.logind = logind;
+            match result {
+                Ok(()) => {}
+                // nothing is left to lock without the compositor
+                Err(e @ Error::Wayland(_)) => return Err(e),
+                Err(e) => log::error!("Locking failed: {}", e),
+            }
+            daemon.resume();
+            daemon.lock_requested = false;
+            daemon.sleep_lock = false;
+            if let Some(logind) = &mut daemon.logind {
+                // ignore requests that were handled by the lock itself
+                logind.process();
+                logind.inhibit();
+            }
+            daemon.arm(&qh);
+        }
+    }
+}
+
+impl Daemon {
+    fn arm(&mut self, qh: &QueueHandle<Daemon>) {
+        let notifier = match &self.notifier {
+            Some(n) => n,
+            None => return,
+        };
+        let seat = self.seat.as_ref().unwrap();
+
+        if !self.config.idle_dim.is_zero() {
+            let timeout = self.config.idle_dim.as_millis() as u32;
+            self.notifications.push(notifier.get_idle_notification(timeout, seat, qh, Stage::Dim));
+        }
+        if !self.config.idle_lock.is_zero() {
+            let timeout = self.config.idle_lock.as_millis() as u32;
+            self.notifications.push(notifier.get_idle_notification(timeout, seat, qh, Stage::Lock));
+        }
+    }
+
+    fn process_logind(&mut self) {
+        let logind = match &mut self.logind {
+            Some(l) => l,
+            None => return,
+        };
+        for event in logind.process() {
+            match event {
+                logind::Event::PrepareForSleep(true) => {
+                    log::info!("Going to sleep, locking");
+                    self.lock_requested = true;
+                    self.sleep_lock = true;
+                }
+                logind::Event::PrepareForSleep(false) => logind.inhibit(),
+                logind::Event::Lock => {
+                    log::info!("Lock requested by logind");
+                    self.lock_requested = true;
+                }
+                logind::Event::Unlock => {}
+            }
+        }
+    }
+
+    fn disarm(&mut self) {
+        for n in self.notifications.drain(..) {
+            n.destroy();
+        }
+    }
+
+    fn resume(&mut self) {
+        if self.dimmed {
+            self.dimmed = false;
+            if let Some(command) = &self.config.idle_resume_command {
+                hook::run(command, &[]);
+            }
+        }
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for Daemon {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+        ) {
+        if let wl_registry::Event::Global {
+            name,
+            version,
+            interface,
+            ..
+        } = event {
+            match &interface[..] {
+                "wl_seat" if state.seat.is_none() => {
+                    let seat = registry.bind::<wl_seat::WlSeat, _, _>(name, version, qh, ());
+                    state.seat = Some(seat);
+                }
+                "ext_idle_notifier_v1" => {
+                    let notifier = registry.bind::<ext_idle_notifier_v1::ExtIdleNotifierV1, _, _>(name, version, qh, ());
+                    state.notifier = Some(notifier);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Dispatch<wl_seat::WlSeat, ()> for Daemon {
+    fn event(
+        _: &mut Self,
+        _: &wl_seat::WlSeat,
+        _: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        ) {
+        // not interested
+    }
+}
+
+impl Dispatch<ext_idle_notifier_v1::ExtIdleNotifierV1, ()> for Daemon {
+    fn event(
+        _: &mut Self,
+        _: &ext_idle_notifier_v1::ExtIdleNotifierV1,
+        _: ext_idle_notifier_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        ) {
+        // no event
+    }
+}
+
+impl Dispatch<ext_idle_notification_v1::ExtIdleNotificationV1, Stage> for Daemon {
+    fn event(
+        state: &mut Self,
+        notification: &ext_idle_notification_v1::ExtIdleNotificationV1,
+        event: ext_idle_notification_v1::Event,
+        stage: &Stage,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        ) {
+        // whatever arrived for notifications destroyed while locking
+        if !state.notifications.contains(notification) {
+            return;
+        }
+        match (event, stage) {
+            (ext_idle_notification_v1::Event::Idled, Stage::Dim) => {
+                state.dimmed = true;
+                if let Some(command) = &state.config.idle_dim_command {
+                    hook::run(command, &[]);
+                }
+            }
+            (ext_idle_notification_v1::Event::Resumed, Stage::Dim) => state.resume(),
+            (ext_idle_notification_v1::Event::Idled, Stage::Lock) => {
+                log::info!("Idle, locking");
+                state.lock_requested = true;
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::{sync::{mpsc, Arc, Mutex}, thread, time::Duration};
+
+use calloop::channel;
+use dbus::{
+    blocking::Connection,
+    channel::{BusType, Channel},
+    message::MatchRule,
+    Path,
+};
+
+const FPRINTD: &str = "net.reactivated.Fprint";
+const MANAGER_PATH: &str = "/net/reactivated/Fprint/Manager";
+const MANAGER_IFACE: &str = "net.reactivated.Fprint.Manager";
+const DEVICE_IFACE: &str = "net.reactivated.Fprint.Device";
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the fprintd thread waits for the bus before looking for restart
+/// requests and the end of the lock.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub enum Event {
+    /// The reader is claimed and waits for a finger.
+    Ready,
+    Match,
+    /// A finger was scanned that is not enrolled, scanning starts over.
+    NoMatch,
+    /// The scan did not work out, with a hint for the user.
+    Retry(&'static str),
+    /// The reader went away or failed, only the password is left.
+    Unavailable,
+}
+
+/// A fingerprint verification through fprintd, running next to password entry.
+/// Every call to fprintd blocks, so it lives on its own thread, see `spawn`.
+pub struct Fprint {
+    conn: Connection,
+    device: Path<'static>,
+    verifying: bool,
+    /// `VerifyStatus` results and whether verification is done.
+    statuses: Arc<Mutex<Vec<(String, bool)>>>,
+}
+
+impl Fprint {
+    /// Claim the default reader for the current user and start verifying.
+    pub fn connect() -> Result<Self, dbus::Error> {
+        Self::with_channel(Channel::get_private(BusType::System)?)
+    }
+
+    /// Like `connect`, with fprintd on the bus at `address`.
+    pub(crate) fn open(address: &str) -> Result<Self, dbus::Error> {
+        let mut channel = Channel::open_private(address)?;
+        channel.register()?;
+        Self::with_channel(channel)
+    }
+
+    fn with_channel(mut channel: Channel) -> Result<Self, dbus::Error> {
+        // needed to get the fd to poll on
+        channel.set_watch_enabled(true);
+        let conn = Connection::from(channel);
+
+        let (device,): (Path<'static>,) = conn.with_proxy(FPRINTD, MANAGER_PATH, TIMEOUT)
+            .method_call(MANAGER_IFACE, "GetDefaultDevice", ())?;
+
+        let statuses = Arc::new(Mutex::new(vec![]));
+        let s = statuses.clone();
+        let rule = MatchRule::new_signal(DEVICE_IFACE, "VerifyStatus").with_path(device.clone());
+        conn.add_match(rule, move |(result, done): (String, bool), _, _| {
+            s.lock().unwrap().push((result, done));
+            true
+        })?;
+
+        // an empty name is the user making the call
+        conn.with_proxy(FPRINTD, device.clone(), TIMEOUT).method_call(DEVICE_IFACE, "Claim", ("",))?;
+
+        let mut fprint = Fprint { conn, device, verifying: false, statuses };
+        fprint.start()?;
+        Ok(fprint)
+    }
+
+    fn start(&mut self) -> Result<(), dbus::Error> {
+        self.conn.with_proxy(FPRINTD, self.device.clone(), TIMEOUT)
+            .method_call(DEVICE_IFACE, "VerifyStart", ("any",))?;
+        self.verifying = true;
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if !self.verifying {
+            return;
+        }
+        self.verifying = false;
+        let result: Result<(), _> = self.conn.with_proxy(FPRINTD, self.device.clone(), TIMEOUT)
+            .method_call(DEVICE_IFACE, "VerifyStop", ());
+        if let Err(e) = result {
+            log::warn!("fprintd: unable to stop verification: {}", e);
+        }
+    }
+
+    /// Start over with a new scan, dropping the one running.
+    fn restart(&mut self) -> Result<(), dbus::Error> {
+        self.stop();
+        self.start()
+    }
+
+    /// Wait up to `timeout` for fprintd, then handle everything that arrived
+    /// on the bus and return the resulting events.
+    fn process(&mut self, mut timeout: Duration) -> Vec<Event> {
+        let mut events = vec![];
+        // restarting can read further signals off the socket
+        loop {
+            self.dispatch(timeout);
+            timeout = Duration::ZERO;
+            let statuses: Vec<_> = self.statuses.lock().unwrap().drain(..).collect();
+            if statuses.is_empty() {
+                return events;
+            }
+            for (result, done) in statuses {
+                self.handle_status(&result, done, &mut events);
+            }
+        }
+    }
+
+    fn dispatch(&mut self, mut timeout: Duration) {
+        loop {
+            match self.conn.process(timeout) {
+                Ok(true) => timeout = Duration::ZERO,
+                Ok(false) => break,
+                Err(e) => {
+                    log::warn!("fprintd: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn handle_status(&mut self, result: &str, done: bool, events: &mut Vec<Event>) {
+        let event = match result {
+            "verify-match" => Event::Match,
+            "verify-no-match" => Event::NoMatch,
+            "verify-retry-scan" => Event::Retry("Scan your finger again"),
+            "verify-swipe-too-short" => Event::Retry("Swipe was too short, try again"),
+            "verify-finger-not-centered" => Event::Retry("Center your finger and try again"),
+            "verify-remove-and-retry" => Event::Retry("Remove your finger and try again"),
+            other => {
+                log::warn!("fprintd: verification failed: {}", other);
+                Event::Unavailable
+            }
+        };
+        let restart = done && matches!(event, Event::NoMatch);
+        events.push(event);
+        if done {
+            self.stop();
+        }
+        if restart {
+            if let Err(e) = self.start() {
+                log::warn!("fprintd: unable to restart verification: {}", e);
+                events.push(Event::Unavailable);
+            }
+        }
+    }
+}
+
+impl Drop for Fprint {
+    fn drop(&mut self) {
+        self.stop();
+        let result: Result<(), _> = self.conn.with_proxy(FPRINTD, self.device.clone(), TIMEOUT)
+            .method_call(DEVICE_IFACE, "Release", ());
+        if let Err(e) = result {
+            log::warn!("fprintd: unable to release reader: {}", e);
+        }
+    }
+}
+
+/// Controls the verification started by `spawn`, dropping it stops it and
+/// releases the reader.
+pub struct Handle {
+    restarts: mpsc::Sender<()>,
+}
+
+impl Handle {
+    /// Start over with a new scan, dropping the one running.
+    pub fn restart(&self) {
+        let _ = self.restarts.send(());
+    }
+}
+
+/// Connect with `connect` and verify fingers on a new thread, so the lock
+/// screen never waits for fprintd. Nothing arrives if connecting fails, the
+/// password still works then.
+pub fn spawn(connect: impl FnOnce() -> Result<Fprint, dbus::Error> + Send + 'static) -> (Handle, channel::Channel<Event>) {
+    let (sender, events) = channel::channel();
+    let (restarts, restart) = mpsc::channel();
+    thread::spawn(move || {
+        let mut fprint = match connect() {
+            Ok(fprint) => fprint,
+            Err(e) => {
+                log::warn!("Unable to use fingerprint reader: {}", e);
+                return;
+            }
+        };
+        if sender.send(Event::Ready).is_err() {
+            return;
+        }
+        loop {
+            match restart.try_recv() {
+                Ok(()) => if let Err(e) = fprint.restart() {
+                    log::warn!("fprintd: unable to restart verification: {}", e);
+                    let _ = sender.send(Event::Unavailable);
+                    return;
+                },
+                Err(mpsc::TryRecvError::Disconnected) => return,
+                Err(mpsc::TryRecvError::Empty) => {}
+            }
+            for event in fprint.process(POLL_INTERVAL) {
+                let unavailable = matches!(event, Event::Unavailable);
+                if sender.send(event).is_err() || unavailable {
+                    return;
+                }
+            }
+        }
+    });
+    (Handle { restarts }, events)
+}
@@ -0,0 +1,109 @@
+use std::{fs::File, io::BufWriter, path::PathBuf};
+
+use crate::{app_data::AppData, config::Config, renderer::Renderer};
+
+const USAGE: &str = "usage: wlock render --output FILE [--size WxH] [--time SECS] [--scale N] [--failed N] [--message TEXT] [--config FILE] [--key=value...]";
+
+/// What `wlock render` draws and where it goes.
+struct Options {
+    output: PathBuf,
+    size: (i32, i32),
+    /// `iTime` of the frame.
+    time: f32,
+    /// Physical pixels per logical one, as on a HiDPI output.
+    scale: f32,
+    failed_attempts: u32,
+    message: Option<String>,
+}
+
+/// `wlock render`: draw one frame of the lock screen offscreen and save it as
+/// PNG, without a compositor or locking anything.
+///
+/// Options other than the ones in `USAGE` are regular config options. The
+/// user's config file is left out unless given with `--config`, so the same
+/// arguments draw the same image on every machine.
+pub fn run(args: Vec<String>) -> Result<(), String> {
+    let mut output = None;
+    let mut config_file = None;
+    let mut options = Options {
+        output: PathBuf::new(),
+        size: (1920, 1080),
+        time: 0.,
+        scale: 1.,
+        failed_attempts: 0,
+        message: None,
+    };
+    let mut config_args = vec![];
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (key, inline) = match arg.split_once('=') {
+            Some((key, value)) => (key.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        if !matches!(&key[..], "--output" | "--size" | "--time" | "--scale" | "--failed" | "--message" | "--config") {
+            config_args.push(arg);
+            continue;
+        }
+        let value = inline.or_else(|| args.next())
+            .ok_or_else(|| format!("missing value for '{}'\n{}", key, USAGE))?;
+        match &key[..] {
+            "--output" => output = Some(PathBuf::from(value)),
+            "--size" => options.size = parse_size(&value)?,
+            "--time" => options.time = parse(&key, &value)?,
+            "--scale" => options.scale = parse(&key, &value)?,
+            "--failed" => options.failed_attempts = parse(&key, &value)?,
+            "--config" => config_file = Some(PathBuf::from(value)),
+            _ => options.message = Some(value).filter(|m| !m.is_empty()),
+        }
+    }
+    options.output = output.ok_or_else(|| USAGE.to_string())?;
+    if options.scale <= 0. {
+        return Err("'--scale' must be positive".to_string());
+    }
+
+    if let Some(path) = config_file.as_ref().filter(|path| !path.is_file()) {
+        return Err(format!("no config file at {}", path.display()));
+    }
+    let config = Config::load_with(config_file.as_deref(), config_args)
+        .map_err(|e| format!("{}\n{}", e, USAGE))?;
+    render(&config, &options)
+}
+
+fn render(config: &Config, options: &Options) -> Result<(), String> {
+    // overlay content comes from the same code as on a real lock surface
+    let mut app_data = AppData::new(config.clone(), None, false);
+    app_data.failed_attempts = options.failed_attempts;
+    app_data.message = options.message.clone();
+    // there is no seat, draw what a lock screen with a pointer shows
+    app_data.show_controls = true;
+
+    let (width, height) = options.size;
+    let mut renderer = Renderer::headless(width, height, &config.shader_source())?;
+    renderer.set_render_scale(config.render_scale);
+    renderer.set_scale(options.scale);
+    renderer.set_overlay(app_data.overlay_rows());
+    renderer.draw((options.time * 1000.) as u32);
+    let pixels = renderer.read_pixels();
+
+    let file = File::create(&options.output)
+        .map_err(|e| format!("unable to create {}: {}", options.output.display(), e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(|e| format!("unable to write {}: {}", options.output.display(), e))
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value '{}' for '{}'", value, key))
+}
+
+/// `WIDTHxHEIGHT` in pixels.
+fn parse_size(value: &str) -> Result<(i32, i32), String> {
+    let size = value.split_once('x')
+        .and_then(|(w, h)| Some((w.parse::<i32>().ok()?, h.parse::<i32>().ok()?)))
+        .filter(|&(w, h)| w > 0 && h > 0);
+    size.ok_or_else(|| format!("invalid size '{}', expected WIDTHxHEIGHT", value))
+}
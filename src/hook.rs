@@ -0,0 +1,29 @@
+use std::{io, os::unix::process::CommandExt, process::Command, ptr, thread};
+
+/// Run a user supplied shell command in the background with extra
+/// environment variables.
+pub fn run(command: &str, env: &[(&str, String)]) {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(k, v)| (k, v)));
+    // signals handled by the event loop are blocked, don't pass that on
+    unsafe {
+        cmd.pre_exec(|| {
+            let mut set = std::mem::zeroed();
+            libc::sigemptyset(&mut set);
+            match libc::sigprocmask(libc::SIG_SETMASK, &set, ptr::null_mut()) {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            }
+        });
+    }
+    let child = cmd.spawn();
+    match child {
+        // reap it without blocking the event loop
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+        }
+        Err(e) => log::warn!("Unable to run '{}': {}", command, e),
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    fs,
+    io::{self, ErrorKind, Read, Write},
+    mem,
+    os::unix::{io::{AsRawFd, RawFd}, net::{UnixListener, UnixStream}},
+    path::{Path, PathBuf},
+};
+
+use calloop::{generic::Generic, Interest, LoopHandle, Mode, PostAction};
+
+/// Commands accepted on the control socket, one per line.
+pub enum Request {
+    /// `status`: reply with the lock state as a JSON object.
+    Status,
+    /// `message <text>`: show text on the lock screen, clears it if empty.
+    Message(String),
+    /// `render`: redraw immediately.
+    Render,
+    /// `quit`: unlock and exit.
+    Quit,
+}
+
+impl Request {
+    fn parse(line: &str) -> Option<Request> {
+        let (cmd, arg) = line.split_once(' ').unwrap_or((line, ""));
+        match cmd {
+            "status" => Some(Request::Status),
+            "message" => Some(Request::Message(arg.to_string())),
+            "render" => Some(Request::Render),
+            "quit" => Some(Request::Quit),
+            _ => None,
+        }
+    }
+}
+
+struct Client {
+    stream: UnixStream,
+    buf: Vec<u8>,
+}
+
+impl Client {
+    /// Answer every complete request line, removes the client once it hung up.
+    fn process(&mut self, mut handle: impl FnMut(Request) -> String) -> PostAction {
+        let mut chunk = [0; 512];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return PostAction::Remove,
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => return PostAction::Remove,
+            }
+        }
+
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let reply = match Request::parse(line.trim_end()) {
+                Some(request) => handle(request),
+                None => "error: unknown command".to_string(),
+            };
+            if self.stream.write_all(format!("{}\n", reply).as_bytes()).is_err() {
+                return PostAction::Remove;
+            }
+        }
+        PostAction::Continue
+    }
+}
+
+impl AsRawFd for Client {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+/// Unix socket that lets processes of the same user query and control the lock.
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl Server {
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(ErrorKind::AddrInUse, "another instance is listening"));
+        }
+        // left behind by an instance that did not exit cleanly
+        let _ = fs::remove_file(path);
+
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Server { listener, path: path.to_path_buf() })
+    }
+
+    /// Serve the socket from the event loop of `handle`, answering requests with `respond`.
+    ///
+    /// The socket is removed again when the event loop is dropped.
+    pub fn insert<'l, D: 'l>(self, handle: &LoopHandle<'l, D>, respond: fn(&mut D, Request) -> String) -> io::Result<()> {
+        let clients = handle.clone();
+        handle.insert_source(Generic::new(self, Interest::READ, Mode::Level), move |_, server, _| {
+            for stream in server.accept() {
+                let client = Generic::new(Client { stream, buf: vec![] }, Interest::READ, Mode::Level);
+                let inserted = clients.insert_source(client, move |_, client, data| {
+                    Ok(client.process(|request| respond(data, request)))
+                });
+                if let Err(e) = inserted {
+                    log::warn!("ipc: {}", e.error);
+                }
+            }
+            Ok(PostAction::Continue)
+        }).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Accept all pending connections from the same user.
+    fn accept(&self) -> Vec<UnixStream> {
+        let mut streams = vec![];
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if !same_user(&stream) {
+                        log::warn!("ipc: rejecting connection from another user");
+                        continue;
+                    }
+                    if stream.set_nonblocking(true).is_ok() {
+                        streams.push(stream);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("ipc: {}", e);
+                    break;
+                }
+            }
+        }
+        streams
+    }
+}
+
+impl AsRawFd for Server {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn same_user(stream: &UnixStream) -> bool {
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+            )
+    };
+    ret == 0 && cred.uid == unsafe { libc::getuid() }
+}
+
+/// Escape `s` for use inside a JSON string.
+pub fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+
+use xkbcommon::xkb;
+
+use crate::widget::{Action, MediaKey};
+
+/// Modifiers held with a key. Shift is usually part of the keysym already, so
+/// it only matters for bindings that ask for it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub logo: bool,
+}
+
+impl Modifiers {
+    pub fn active(state: &xkb::State) -> Self {
+        let active = |name: &str| state.mod_name_is_active(name, xkb::STATE_MODS_EFFECTIVE);
+        Modifiers {
+            ctrl: active(xkb::MOD_NAME_CTRL),
+            alt: active(xkb::MOD_NAME_ALT),
+            shift: active(xkb::MOD_NAME_SHIFT),
+            logo: active(xkb::MOD_NAME_LOGO),
+        }
+    }
+
+    fn count(self) -> usize {
+        [self.ctrl, self.alt, self.shift, self.logo].iter().filter(|&&m| m).count()
+    }
+
+    /// Whether everything in `self` is held in `active`.
+    fn held_in(self, active: Modifiers) -> bool {
+        (!self.ctrl || active.ctrl) && (!self.alt || active.alt)
+            && (!self.shift || active.shift) && (!self.logo || active.logo)
+    }
+}
+
+/// A key with modifiers and what pressing it does, `None` disables a default.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Binding {
+    pub keysym: xkb::Keysym,
+    pub modifiers: Modifiers,
+    pub action: Option<Action>,
+}
+
+impl Binding {
+    /// Parse `Ctrl+Shift+w delete-word` style config values.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (keys, action) = value.split_once(char::is_whitespace)
+            .ok_or_else(|| format!("binding '{}' needs a key and an action", value))?;
+        let action = match action.trim() {
+            "none" => None,
+            name => Some(parse_action(name).ok_or_else(|| format!("unknown action '{}'", name))?),
+        };
+
+        let mut modifiers = Modifiers::default();
+        let mut parts: Vec<_> = keys.split('+').collect();
+        let key = parts.pop().unwrap_or_default();
+        for part in parts {
+            match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => modifiers.ctrl = true,
+                "alt" => modifiers.alt = true,
+                "shift" => modifiers.shift = true,
+                "super" | "logo" => modifiers.logo = true,
+                _ => return Err(format!("unknown modifier '{}'", part)),
+            }
+        }
+        // keys are looked up without Shift and Caps Lock, so `Ctrl+U` means `Ctrl+u`
+        let key = match key.chars().count() {
+            1 => key.to_lowercase(),
+            _ => key.to_string(),
+        };
+        let keysym = xkb::keysym_from_name(&key, xkb::KEYSYM_NO_FLAGS);
+        if keysym == xkb::KEY_NoSymbol {
+            return Err(format!("unknown key '{}'", key));
+        }
+        Ok(Binding { keysym, modifiers, action })
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Modifiers { ctrl, alt, shift, logo } = self.modifiers;
+        for (held, name) in [(ctrl, "Ctrl"), (alt, "Alt"), (shift, "Shift"), (logo, "Super")] {
+            if held {
+                write!(f, "{}+", name)?;
+            }
+        }
+        write!(f, "{}", xkb::keysym_get_name(self.keysym))
+    }
+}
+
+fn bind(keysym: xkb::Keysym, ctrl: bool, action: Action) -> Binding {
+    Binding { keysym, modifiers: Modifiers { ctrl, ..Modifiers::default() }, action: Some(action) }
+}
+
+/// Bindings before the config file adds its own.
+pub fn defaults() -> Vec<Binding> {
+    vec![
+        bind(xkb::KEY_Return, false, Action::Submit),
+        bind(xkb::KEY_KP_Enter, false, Action::Submit),
+        bind(xkb::KEY_Escape, false, Action::Clear),
+        bind(xkb::KEY_u, true, Action::Clear),
+        bind(xkb::KEY_BackSpace, false, Action::Backspace),
+        bind(xkb::KEY_BackSpace, true, Action::DeleteWord),
+        bind(xkb::KEY_w, true, Action::DeleteWord),
+        bind(xkb::KEY_Delete, false, Action::Delete),
+        bind(xkb::KEY_KP_Delete, false, Action::Delete),
+        bind(xkb::KEY_Left, false, Action::Left),
+        bind(xkb::KEY_KP_Left, false, Action::Left),
+        bind(xkb::KEY_Right, false, Action::Right),
+        bind(xkb::KEY_KP_Right, false, Action::Right),
+        bind(xkb::KEY_Home, false, Action::Home),
+        bind(xkb::KEY_KP_Home, false, Action::Home),
+        bind(xkb::KEY_a, true, Action::Home),
+        bind(xkb::KEY_End, false, Action::End),
+        bind(xkb::KEY_KP_End, false, Action::End),
+        bind(xkb::KEY_e, true, Action::End),
+        bind(xkb::KEY_space, true, Action::CycleLayout),
+        bind(xkb::KEY_r, true, Action::ToggleReveal),
+        bind(xkb::KEY_F1, false, Action::ToggleHelp),
+        bind(xkb::KEY_XF86AudioPlay, false, Action::Media(MediaKey::PlayPause)),
+        bind(xkb::KEY_XF86AudioPause, false, Action::Media(MediaKey::PlayPause)),
+        bind(xkb::KEY_XF86AudioNext, false, Action::Media(MediaKey::Next)),
+        bind(xkb::KEY_XF86AudioPrev, false, Action::Media(MediaKey::Previous)),
+    ]
+}
+
+/// Add `binding`, replacing one for the same keys.
+pub fn add(bindings: &mut Vec<Binding>, binding: Binding) {
+    bindings.retain(|b| (b.keysym, b.modifiers) != (binding.keysym, binding.modifiers));
+    bindings.push(binding);
+}
+
+/// The action bound to `keysym` with `active` held. Extra modifiers are
+/// fine, the binding asking for the most of them wins.
+pub fn lookup(bindings: &[Binding], keysym: xkb::Keysym, active: Modifiers) -> Option<Action> {
+    find(bindings, keysym, active).and_then(|b| b.action)
+}
+
+fn find(bindings: &[Binding], keysym: xkb::Keysym, active: Modifiers) -> Option<&Binding> {
+    bindings.iter()
+        .filter(|b| b.keysym == keysym && b.modifiers.held_in(active))
+        .max_by_key(|b| b.modifiers.count())
+}
+
+/// The action bound to `keycode` pressed in `state`. If what the key types has
+/// no binding, it is tried without Shift and Caps Lock, so Caps Lock does not
+/// break Ctrl+u and `Shift+w` can match. With Ctrl, Alt or Super held the other
+/// layouts are tried too, for bindings to work on non-Latin layouts.
+pub fn lookup_key(bindings: &[Binding], keymap: &xkb::Keymap, state: &xkb::State, keycode: xkb::Keycode) -> Option<Action> {
+    let active = Modifiers::active(state);
+    let mut keysyms = vec![state.key_get_one_sym(keycode)];
+
+    let bit = |name| 1u32.checked_shl(keymap.mod_get_index(name)).unwrap_or(0);
+    let plain = !(bit(xkb::MOD_NAME_SHIFT) | bit(xkb::MOD_NAME_CAPS));
+    let depressed = state.serialize_mods(xkb::STATE_MODS_DEPRESSED) & plain;
+    let latched = state.serialize_mods(xkb::STATE_MODS_LATCHED) & plain;
+    let locked = state.serialize_mods(xkb::STATE_MODS_LOCKED) & plain;
+    let layout = state.serialize_layout(xkb::STATE_LAYOUT_EFFECTIVE);
+    let mut layouts = vec![layout];
+    if active.ctrl || active.alt || active.logo {
+        layouts.extend((0..keymap.num_layouts()).filter(|&l| l != layout));
+    }
+    let mut unshifted = xkb::State::new(keymap);
+    for layout in layouts {
+        unshifted.update_mask(depressed, latched, locked, 0, 0, layout);
+        keysyms.push(unshifted.key_get_one_sym(keycode));
+    }
+
+    // a binding disabled with `none` stops the search too
+    keysyms.into_iter()
+        .find(|&keysym| find(bindings, keysym, active).is_some())
+        .and_then(|keysym| lookup(bindings, keysym, active))
+}
+
+fn parse_action(name: &str) -> Option<Action> {
+    Some(match name {
+        "submit" => Action::Submit,
+        "clear" => Action::Clear,
+        "backspace" => Action::Backspace,
+        "delete-word" => Action::DeleteWord,
+        "delete" => Action::Delete,
+        "left" => Action::Left,
+        "right" => Action::Right,
+        "home" => Action::Home,
+        "end" => Action::End,
+        "cycle-layout" => Action::CycleLayout,
+        "toggle-reveal" => Action::ToggleReveal,
+        "toggle-keyboard" => Action::ToggleKeyboard,
+        "toggle-user" => Action::ToggleUser,
+        "toggle-help" => Action::ToggleHelp,
+        "fingerprint" => Action::Fingerprint,
+        "security-key" => Action::SecurityKey,
+        "play-pause" => Action::Media(MediaKey::PlayPause),
+        "next" => Action::Media(MediaKey::Next),
+        "previous" => Action::Media(MediaKey::Previous),
+        _ => return None,
+    })
+}
+
+/// Name of `action` as used in the config file.
+pub fn action_name(action: Action) -> &'static str {
+    match action {
+        Action::Submit => "submit",
+        Action::Clear => "clear",
+        Action::Backspace => "backspace",
+        Action::DeleteWord => "delete-word",
+        Action::Delete => "delete",
+        Action::Left => "left",
+        Action::Right => "right",
+        Action::Home => "home",
+        Action::End => "end",
+        Action::Key(_) => "type",
+        Action::Shift => "shift",
+        Action::CycleLayout => "cycle-layout",
+        Action::ToggleReveal => "toggle-reveal",
+        Action::ToggleKeyboard => "toggle-keyboard",
+        Action::ToggleUser => "toggle-user",
+        Action::ToggleHelp => "toggle-help",
+        Action::Fingerprint => "fingerprint",
+        Action::SecurityKey => "security-key",
+        Action::Media(MediaKey::PlayPause) => "play-pause",
+        Action::Media(MediaKey::Next) => "next",
+        Action::Media(MediaKey::Previous) => "previous",
+    }
+}
@@ -0,0 +1,38 @@
+//! Session locking for Wayland compositors supporting ext-session-lock-v1.
+//!
+//! `LockSession` runs a lock on a connection, with the password check and the
+//! drawing pluggable through `Authenticator` and `LockRenderer`. The `wlock`
+//! binary is a thin wrapper around it.
+
+mod app_data;
+pub mod auth;
+pub mod config;
+mod cursor;
+pub mod daemon;
+mod fprint;
+pub mod headless;
+mod hook;
+mod ipc;
+mod keys;
+mod logind;
+mod media;
+mod notify;
+mod osk;
+mod password;
+mod power;
+pub mod renderer;
+pub mod security_key;
+mod session;
+pub mod state;
+pub mod text;
+mod widget;
+
+pub use auth::{Authenticator, Pam, Shadow};
+pub use config::{AuthBackend, Config, EchoMode};
+pub use renderer::{LockRenderer, Renderer};
+pub use security_key::SecurityKey;
+pub use session::{Error, Event, LockSession, UnlockHandle};
+pub use state::LockState;
+
+#[cfg(test)]
+mod tests;
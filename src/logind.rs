@@ -0,0 +1,142 @@
+use std::{cell::RefCell, env, os::unix::io::RawFd, rc::Rc, time::Duration};
+
+use dbus::{
+    arg::OwnedFd,
+    blocking::LocalConnection,
+    channel::{BusType, Channel},
+    message::MatchRule,
+    Path,
+};
+
+const LOGIND: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_IFACE: &str = "org.freedesktop.login1.Manager";
+const SESSION_IFACE: &str = "org.freedesktop.login1.Session";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub enum Event {
+    /// `true` right before suspending, `false` after resuming.
+    PrepareForSleep(bool),
+    /// `loginctl lock-session`
+    Lock,
+    /// `loginctl unlock-session`
+    Unlock,
+}
+
+/// Connection to systemd-logind for the session wlock runs in.
+pub struct Logind {
+    conn: LocalConnection,
+    session: Path<'static>,
+    inhibitor: Option<OwnedFd>,
+    locked_hint: bool,
+    events: Rc<RefCell<Vec<Event>>>,
+}
+
+impl Logind {
+    pub fn connect() -> Result<Self, dbus::Error> {
+        Self::with_channel(Channel::get_private(BusType::System)?)
+    }
+
+    /// Like `connect`, with logind on the bus at `address`.
+    pub(crate) fn open(address: &str) -> Result<Self, dbus::Error> {
+        let mut channel = Channel::open_private(address)?;
+        channel.register()?;
+        Self::with_channel(channel)
+    }
+
+    fn with_channel(mut channel: Channel) -> Result<Self, dbus::Error> {
+        // needed to get the fd to poll on
+        channel.set_watch_enabled(true);
+        let conn = LocalConnection::from(channel);
+
+        let id = env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".to_string());
+        let (session,): (Path<'static>,) = conn.with_proxy(LOGIND, MANAGER_PATH, TIMEOUT)
+            .method_call(MANAGER_IFACE, "GetSession", (id,))?;
+
+        let events = Rc::new(RefCell::new(vec![]));
+
+        let e = events.clone();
+        let rule = MatchRule::new_signal(MANAGER_IFACE, "PrepareForSleep").with_path(MANAGER_PATH);
+        conn.add_match(rule, move |(start,): (bool,), _, _| {
+            e.borrow_mut().push(Event::PrepareForSleep(start));
+            true
+        })?;
+
+        let e = events.clone();
+        let rule = MatchRule::new_signal(SESSION_IFACE, "Lock").with_path(session.clone());
+        conn.add_match(rule, move |_: (), _, _| {
+            e.borrow_mut().push(Event::Lock);
+            true
+        })?;
+
+        let e = events.clone();
+        let rule = MatchRule::new_signal(SESSION_IFACE, "Unlock").with_path(session.clone());
+        conn.add_match(rule, move |_: (), _, _| {
+            e.borrow_mut().push(Event::Unlock);
+            true
+        })?;
+
+        Ok(Logind {
+            conn,
+            session,
+            inhibitor: None,
+            locked_hint: false,
+            events,
+        })
+    }
+
+    /// File descriptor to wait on for new events.
+    pub fn fd(&self) -> RawFd {
+        self.conn.channel().watch().fd
+    }
+
+    /// Handle everything that arrived on the bus and return the resulting events.
+    pub fn process(&mut self) -> Vec<Event> {
+        loop {
+            match self.conn.process(Duration::ZERO) {
+                Ok(true) => continue,
+                Ok(false) => break,
+                Err(e) => {
+                    log::warn!("logind: {}", e);
+                    break;
+                }
+            }
+        }
+        self.events.borrow_mut().drain(..).collect()
+    }
+
+    /// Take a delay inhibitor so suspend waits until the session is locked.
+    pub fn inhibit(&mut self) {
+        if self.inhibitor.is_some() {
+            return;
+        }
+        let result: Result<(OwnedFd,), _> = self.conn.with_proxy(LOGIND, MANAGER_PATH, TIMEOUT)
+            .method_call(MANAGER_IFACE, "Inhibit", ("sleep", "wlock", "Lock screen before sleep", "delay"));
+        match result {
+            Ok((fd,)) => self.inhibitor = Some(fd),
+            Err(e) => log::warn!("logind: unable to take inhibitor lock: {}", e),
+        }
+    }
+
+    /// Called once the session is locked, lets a pending suspend continue.
+    pub fn locked(&mut self) {
+        self.inhibitor = None;
+        self.set_locked_hint(true);
+    }
+
+    pub fn unlocked(&mut self) {
+        self.set_locked_hint(false);
+    }
+
+    fn set_locked_hint(&mut self, locked: bool) {
+        if self.locked_hint == locked {
+            return;
+        }
+        let result: Result<(), _> = self.conn.with_proxy(LOGIND, self.session.clone(), TIMEOUT)
+            .method_call(SESSION_IFACE, "SetLockedHint", (locked,));
+        match result {
+            Ok(()) => self.locked_hint = locked,
+            Err(e) => log::warn!("logind: unable to set locked hint: {}", e),
+        }
+    }
+}
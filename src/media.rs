@@ -0,0 +1,37 @@
+use std::{thread, time::Duration};
+
+use dbus::blocking::Connection;
+
+use crate::widget::MediaKey;
+
+const DBUS: &str = "org.freedesktop.DBus";
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const PLAYER_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_IFACE: &str = "org.mpris.MediaPlayer2.Player";
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Send `key` to the first media player found on the session bus. Runs on
+/// its own thread, so a slow bus or player never holds up the lock screen.
+pub fn send(key: MediaKey) {
+    thread::spawn(move || {
+        if let Err(e) = try_send(key) {
+            log::warn!("Unable to control media player: {}", e);
+        }
+    });
+}
+
+fn try_send(key: MediaKey) -> Result<(), dbus::Error> {
+    let conn = Connection::new_session()?;
+    let (names,): (Vec<String>,) = conn.with_proxy(DBUS, "/org/freedesktop/DBus", TIMEOUT)
+        .method_call(DBUS, "ListNames", ())?;
+    let player = match names.into_iter().find(|name| name.starts_with(MPRIS_PREFIX)) {
+        Some(player) => player,
+        None => return Ok(()),
+    };
+    let method = match key {
+        MediaKey::PlayPause => "PlayPause",
+        MediaKey::Next => "Next",
+        MediaKey::Previous => "Previous",
+    };
+    conn.with_proxy(player, PLAYER_PATH, TIMEOUT).method_call(PLAYER_IFACE, method, ())
+}
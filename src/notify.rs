@@ -0,0 +1,23 @@
+use std::time::Duration;
+
+use dbus::{arg::PropMap, blocking::Connection};
+
+const NOTIFICATIONS: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Show a desktop notification through the notification daemon on the session bus.
+pub fn send(summary: &str, body: &str) {
+    if let Err(e) = try_send(summary, body) {
+        log::warn!("Unable to show notification: {}", e);
+    }
+}
+
+fn try_send(summary: &str, body: &str) -> Result<(), dbus::Error> {
+    let conn = Connection::new_session()?;
+    let actions: Vec<&str> = vec![];
+    // -1 lets the notification daemon pick how long it stays
+    let (_id,): (u32,) = conn.with_proxy(NOTIFICATIONS, NOTIFICATIONS_PATH, TIMEOUT)
+        .method_call(NOTIFICATIONS, "Notify", ("wlock", 0u32, "", summary, body, actions, PropMap::new(), -1i32))?;
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use xkbcommon::xkb;
+
+use crate::widget::{Action, Widget};
+
+/// evdev keycodes of the character keys, row by row as on a physical keyboard.
+const ROWS: [&[u32]; 4] = [
+    &[2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13],
+    &[16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27],
+    &[30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40],
+    &[44, 45, 46, 47, 48, 49, 50, 51, 52, 53],
+];
+
+/// What the keys of `ROWS` type on a US layout, unshifted and shifted, for
+/// when not even the default keymap can be compiled.
+const US_ROWS: [[&str; 2]; 4] = [
+    ["1234567890-=", "!@#$%^&*()_+"],
+    ["qwertyuiop[]", "QWERTYUIOP{}"],
+    ["asdfghjkl;'", "ASDFGHJKL:\""],
+    ["zxcvbnm,./", "ZXCVBNM<>?"],
+];
+
+const KEY_SPACE: u32 = 57;
+
+/// On-screen keyboard for devices without a physical one.
+pub struct Osk {
+    pub visible: bool,
+    /// One-shot shift, released after the next key.
+    pub shift: bool,
+    /// Used for labels when the seat has no keyboard and so no keymap,
+    /// `Some(None)` once compiling it failed.
+    fallback_keymap: Option<Option<xkb::Keymap>>,
+}
+
+impl Osk {
+    pub fn new() -> Self {
+        Osk { visible: false, shift: false, fallback_keymap: None }
+    }
+
+    /// Keyboard rows, labeled using the active layout of `state` if there is one.
+    pub fn widgets(&mut self, context: &xkb::Context, state: Option<&xkb::State>) -> Vec<Vec<Widget>> {
+        let mut rows: Vec<Vec<Widget>> = ROWS.iter()
+            .map(|row| row.iter()
+                 .map(|&code| Widget::Button(self.key_text(context, state, code), Action::Key(code)))
+                 .collect())
+            .collect();
+        rows.push(vec![
+            Widget::Button(if self.shift { "SHIFT" } else { "Shift" }.to_string(), Action::Shift),
+            Widget::Button("Space".to_string(), Action::Key(KEY_SPACE)),
+            Widget::Button("\u{232b}".to_string(), Action::Backspace),
+            Widget::Button("Enter".to_string(), Action::Submit),
+        ]);
+        rows
+    }
+
+    /// Text produced by the evdev keycode `code`, honoring the one-shot shift.
+    pub fn key_text(&mut self, context: &xkb::Context, state: Option<&xkb::State>, code: u32) -> String {
+        let layout = state.map_or(0, |s| s.serialize_layout(xkb::STATE_LAYOUT_EFFECTIVE));
+        let level = if self.shift { 1 } else { 0 };
+        let state_keymap;
+        let keymap = match state {
+            Some(s) => {
+                state_keymap = s.get_keymap();
+                &state_keymap
+            }
+            None => match self.fallback_keymap(context) {
+                Some(keymap) => keymap,
+                None => return us_key_text(code, level as usize),
+            },
+        };
+        // wayland keycodes are offset by 8 from xkb keycodes
+        keymap.key_get_syms_by_level(code + 8, layout, level).first()
+            .map(|&sym| xkb::keysym_to_utf8(sym).trim_end_matches('\0').to_string())
+            .unwrap_or_default()
+    }
+
+    fn fallback_keymap(&mut self, context: &xkb::Context) -> Option<&xkb::Keymap> {
+        // empty names pick the system defaults, see XKB_DEFAULT_LAYOUT
+        self.fallback_keymap.get_or_insert_with(|| {
+            let keymap = xkb::Keymap::new_from_names(context, "", "", "", "", None, xkb::KEYMAP_COMPILE_NO_FLAGS);
+            if keymap.is_none() {
+                log::warn!("Unable to compile the default keymap, the on-screen keyboard uses a US layout");
+            }
+            keymap
+        }).as_ref()
+    }
+}
+
+/// Text of the evdev keycode `code` at shift level `level` on a US layout.
+pub(crate) fn us_key_text(code: u32, level: usize) -> String {
+    if code == KEY_SPACE {
+        return " ".to_string();
+    }
+    ROWS.iter().zip(US_ROWS)
+        .find_map(|(row, text)| {
+            let i = row.iter().position(|&c| c == code)?;
+            text[level].chars().nth(i)
+        })
+        .map_or(String::new(), String::from)
+}
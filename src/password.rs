@@ -0,0 +1,102 @@
+use std::{collections::hash_map::RandomState, hash::{BuildHasher, Hasher}};
+
+/// The password being typed, with a cursor that edits happen at.
+#[derive(Default)]
+pub struct Password {
+    text: String,
+    /// Byte offset into `text`, always on a char boundary.
+    cursor: usize,
+    /// Drawn anew on every edit, so retyping the same text gives a new value.
+    noise: u64,
+}
+
+impl Password {
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Number of characters, what the bullets stand for.
+    pub fn len(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    /// Random number for echo modes that hide the length.
+    pub fn noise(&self) -> u64 {
+        self.noise
+    }
+
+    /// Cursor position in characters.
+    pub fn cursor(&self) -> usize {
+        self.text[..self.cursor].chars().count()
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+        self.edited();
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+        self.edited();
+    }
+
+    /// Delete the character before the cursor.
+    pub fn backspace(&mut self) {
+        let start = self.prev_boundary();
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+        self.edited();
+    }
+
+    /// Delete the character under the cursor.
+    pub fn delete(&mut self) {
+        let end = self.next_boundary();
+        self.text.replace_range(self.cursor..end, "");
+        self.edited();
+    }
+
+    /// Delete back to the start of the word before the cursor, like readline's Ctrl+W.
+    pub fn delete_word(&mut self) {
+        let start = self.text[..self.cursor].trim_end().char_indices()
+            .rfind(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+        self.edited();
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.prev_boundary();
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = self.next_boundary();
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    fn edited(&mut self) {
+        // every RandomState is keyed differently, hashing nothing is enough
+        self.noise = RandomState::new().build_hasher().finish();
+    }
+
+    fn prev_boundary(&self) -> usize {
+        self.text[..self.cursor].char_indices().next_back().map_or(0, |(i, _)| i)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text[self.cursor..].chars().next().map_or(self.cursor, |c| self.cursor + c.len_utf8())
+    }
+}
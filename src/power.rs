@@ -0,0 +1,16 @@
+use std::fs;
+
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// Whether any battery reports that it is discharging.
+pub fn on_battery() -> bool {
+    let entries = match fs::read_dir(POWER_SUPPLY) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries.flatten().any(|entry| {
+        let path = entry.path();
+        let read = |name| fs::read_to_string(path.join(name)).unwrap_or_default();
+        read("type").trim() == "Battery" && read("status").trim() == "Discharging"
+    })
+}
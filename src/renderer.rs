@@ -5,36 +5,151 @@ use gl::types::{GLenum, GLuint, GLint, GLchar, GLboolean, GLvoid};
 use khronos_egl as egl;
 use wayland_client::{protocol::{wl_display, wl_surface}, Proxy};
 
+use crate::{config::Config, text::{Item, Rect, TextRasterizer}};
+
+/// Draws the lock screen on one surface. Sizes and positions are in physical pixels.
+pub trait LockRenderer {
+    /// Replace the overlay content, rows of items centered on the surface.
+    fn set_overlay(&mut self, rows: Vec<Vec<Item>>);
+
+    /// Draw a frame `dt` milliseconds into the animation and show it.
+    fn render(&self, dt: u32);
+
+    fn size(&self) -> (i32, i32);
+
+    fn resize(&mut self, width: i32, height: i32);
+
+    /// Find the overlay item at surface position `x`, `y`, as row and column.
+    fn hit_test(&self, _x: f64, _y: f64) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// Shadertoy style `iMouse`: position while a button is held and where it
+    /// was pressed, negative once released.
+    fn set_mouse(&mut self, _mouse: [f32; 4]) {}
+
+    /// Physical pixels per logical one.
+    fn set_scale(&mut self, _scale: f32) {}
+
+    /// Switch to another `mainImage` function, `false` if it can not be used.
+    fn set_shader(&mut self, _shader: &str) -> bool {
+        false
+    }
+
+    /// Fraction of the surface size expensive parts may be drawn at.
+    fn set_render_scale(&mut self, _scale: f32) {}
+}
+
+/// Creates the renderer for a lock surface, given its size in physical pixels.
+pub type NewRenderer = Box<dyn FnMut(&wl_display::WlDisplay, &wl_surface::WlSurface, (i32, i32), &Config) -> Box<dyn LockRenderer>>;
+
+/// The default `NewRenderer`, drawing `Config::shader` with EGL.
+pub fn new_egl(display: &wl_display::WlDisplay, surface: &wl_surface::WlSurface, (width, height): (i32, i32), config: &Config) -> Box<dyn LockRenderer> {
+    let mut renderer = Renderer::new(display, surface, width, height, &config.shader_source());
+    renderer.set_render_scale(config.render_scale);
+    Box::new(renderer)
+}
+
+/// `LockRenderer` drawing a Shadertoy style shader with a text overlay using OpenGL.
 pub struct Renderer {
     egl: egl::Instance<egl::Static>,
-    wl_egl_surface: wayland_egl::WlEglSurface,
+    /// `None` when drawing offscreen.
+    wl_egl_surface: Option<wayland_egl::WlEglSurface>,
     egl_surface: egl::Surface,
     egl_display: egl::Display,
     egl_context: egl::Context,
     width: i32,
     height: i32,
 
+    program: GLuint,
+    vao: GLuint,
     u_time: GLint,
     u_res: GLint,
     u_hms: GLint,
+    u_mouse: GLint,
+    mouse: [f32; 4],
+
+    /// Fraction of the surface size the shader is drawn at.
+    render_scale: f32,
+    /// Offscreen target for the shader while `render_scale` is below 1.
+    target: Option<Target>,
+
+    text: TextRasterizer,
+    /// Physical pixels per logical one, text is sized accordingly.
+    scale: f32,
+    overlay: Overlay,
+}
+
+/// Overlay text size in logical pixels.
+const TEXT_SIZE: f32 = 32.;
+
+/// `EGL_PLATFORM_SURFACELESS_MESA` from EGL_MESA_platform_surfaceless.
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+/// Framebuffer the shader is drawn into before being upscaled to the surface.
+struct Target {
+    fbo: GLuint,
+    texture: GLuint,
+    width: i32,
+    height: i32,
+}
+
+/// Text drawn on top of the shader, centered on the surface.
+struct Overlay {
+    program: GLuint,
+    vao: GLuint,
+    texture: GLuint,
+    u_rect: GLint,
+    rows: Vec<Vec<Item>>,
+    rects: Vec<Vec<Rect>>,
+    width: i32,
+    height: i32,
 }
 
 impl Renderer {
-    pub fn new(display: &wl_display::WlDisplay, surface: &wl_surface::WlSurface, width: i32, height: i32) -> Self {
-        // Create an EGL API instance.
-        let egl = egl::Instance::new(egl::Static);
-        egl.bind_api(egl::OPENGL_API).expect("unable to select OpenGL API");
-        gl::load_with(|name| egl.get_proc_address(name).unwrap() as *const std::ffi::c_void);
+    /// `shader` is the `mainImage` function to draw, see `DEFAULT_SHADER`.
+    pub fn new(display: &wl_display::WlDisplay, surface: &wl_surface::WlSurface, width: i32, height: i32, shader: &str) -> Self {
+        let egl = create_instance();
 
         // Setup EGL.
         let egl_display = setup_egl(&egl, display);
-        let (egl_context, egl_config) = create_context(&egl, egl_display);
+        let (egl_context, egl_config) = create_context(&egl, egl_display, egl::WINDOW_BIT);
 
         // Create a surface.
         // Note that it must be kept alive to the end of execution.
         let (wl_egl_surface, egl_surface) = setup_surface(&egl, surface, width, height, egl_display, egl_config);
 
+        Self::with_surface(egl, egl_display, egl_context, egl_surface, Some(wl_egl_surface), (width, height), shader)
+    }
+
+    /// Draw into an offscreen pbuffer instead of a Wayland surface, see
+    /// `read_pixels`. Uses Mesa's surfaceless platform, which needs neither a
+    /// Wayland nor an X server.
+    pub fn headless(width: i32, height: i32, shader: &str) -> Result<Self, String> {
+        let egl = create_instance();
+
+        let egl_display = unsafe {
+            egl.get_platform_display(PLATFORM_SURFACELESS_MESA, egl::DEFAULT_DISPLAY, &[egl::ATTRIB_NONE])
+        }.map_err(|e| format!("EGL_MESA_platform_surfaceless is not available: {}", e))?;
+        egl.initialize(egl_display).map_err(|e| format!("unable to initialize EGL: {}", e))?;
+        let (egl_context, egl_config) = create_context(&egl, egl_display, egl::PBUFFER_BIT);
+
+        let attributes = [egl::WIDTH, width, egl::HEIGHT, height, egl::NONE];
+        let egl_surface = egl.create_pbuffer_surface(egl_display, egl_config, &attributes)
+            .map_err(|e| format!("unable to create an EGL pbuffer: {}", e))?;
 
+        Ok(Self::with_surface(egl, egl_display, egl_context, egl_surface, None, (width, height), shader))
+    }
+
+    fn with_surface(
+        egl: egl::Instance<egl::Static>,
+        egl_display: egl::Display,
+        egl_context: egl::Context,
+        egl_surface: egl::Surface,
+        wl_egl_surface: Option<wayland_egl::WlEglSurface>,
+        (width, height): (i32, i32),
+        shader: &str,
+        ) -> Self {
         let mut renderer = Renderer {
             egl,
             wl_egl_surface,
@@ -44,16 +159,42 @@ impl Renderer {
             width,
             height,
 
+            program: 0,
+            vao: 0,
             u_time: -1,
             u_res: -1,
             u_hms: -1,
+            u_mouse: -1,
+            mouse: [0.; 4],
+
+            render_scale: 1.,
+            target: None,
+
+            text: TextRasterizer::new(TEXT_SIZE),
+            scale: 1.,
+            overlay: Overlay {
+                program: 0,
+                vao: 0,
+                texture: 0,
+                u_rect: -1,
+                rows: vec![],
+                rects: vec![],
+                width: 0,
+                height: 0,
+            },
         };
 
         renderer.make_current();
-        let uniforms = compile_program();
-        renderer.u_time = uniforms.0;
-        renderer.u_res = uniforms.1;
-        renderer.u_hms = uniforms.2;
+        renderer.vao = create_quad();
+        if !renderer.set_shader(shader) && !renderer.set_shader(DEFAULT_SHADER) {
+            panic!("default shader compilation failed");
+        }
+
+        let overlay = compile_overlay_program();
+        renderer.overlay.program = overlay.0;
+        renderer.overlay.vao = overlay.1;
+        renderer.overlay.texture = overlay.2;
+        renderer.overlay.u_rect = overlay.3;
 
         renderer
     }
@@ -63,10 +204,120 @@ impl Renderer {
             .expect("unable to bind the context");
     }
 
-    pub fn render(&self, dt: u32) {
+    /// Replace the overlay content, the texture is only updated if it changed.
+    pub fn set_overlay(&mut self, rows: Vec<Vec<Item>>) {
+        if rows == self.overlay.rows {
+            return;
+        }
         self.make_current();
 
-        render(self.width, self.height, self.u_time, self.u_res, self.u_hms, dt);
+        let (bitmap, rects) = self.text.rasterize(&rows);
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.overlay.texture);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage2D(
+                gl::TEXTURE_2D, 0, gl::R8 as GLint,
+                bitmap.width as i32, bitmap.height as i32, 0,
+                gl::RED, gl::UNSIGNED_BYTE, bitmap.data.as_ptr() as *const GLvoid
+                );
+            check_gl_errors();
+        }
+        self.overlay.width = bitmap.width as i32;
+        self.overlay.height = bitmap.height as i32;
+        self.overlay.rows = rows;
+        self.overlay.rects = rects;
+    }
+
+    /// Switch to another `mainImage` function. On compile errors the log is
+    /// printed, the current program is kept and `false` is returned.
+    pub fn set_shader(&mut self, shader: &str) -> bool {
+        self.make_current();
+        let (program, u_time, u_res, u_hms, u_mouse) = match compile_program(shader) {
+            Ok(uniforms) => uniforms,
+            Err(log) => {
+                log::warn!("shader compilation failed:\n{}", log);
+                return false;
+            }
+        };
+        if self.program != 0 {
+            unsafe { gl::DeleteProgram(self.program) };
+        }
+        self.program = program;
+        self.u_time = u_time;
+        self.u_res = u_res;
+        self.u_hms = u_hms;
+        self.u_mouse = u_mouse;
+        true
+    }
+
+    /// Rasterize text for `scale` physical pixels per logical one.
+    pub fn set_scale(&mut self, scale: f32) {
+        if scale == self.scale {
+            return;
+        }
+        self.scale = scale;
+        self.text = TextRasterizer::new(TEXT_SIZE * scale);
+        // forces the texture to be redone on the next set_overlay
+        self.overlay.rows.clear();
+        self.overlay.width = 0;
+        self.overlay.height = 0;
+    }
+
+    /// Draw the shader at `scale` times the surface size and upscale it,
+    /// the overlay stays at full resolution.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        if scale == self.render_scale {
+            return;
+        }
+        self.render_scale = scale;
+        self.update_target();
+    }
+
+    /// Create, resize or drop the offscreen target to match the surface and `render_scale`.
+    fn update_target(&mut self) {
+        self.make_current();
+        if self.render_scale >= 1. {
+            if let Some(target) = self.target.take() {
+                delete_target(&target);
+            }
+            return;
+        }
+        let width = ((self.width as f32 * self.render_scale).round() as i32).max(1);
+        let height = ((self.height as f32 * self.render_scale).round() as i32).max(1);
+        if let Some(target) = &self.target {
+            if (target.width, target.height) == (width, height) {
+                return;
+            }
+        }
+        if let Some(target) = self.target.take() {
+            delete_target(&target);
+        }
+        self.target = Some(create_target(width, height));
+    }
+
+    /// Size in physical pixels.
+    pub fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    /// Find the overlay item at surface position `x`, `y`, as row and column.
+    pub fn hit_test(&self, x: f64, y: f64) -> Option<(usize, usize)> {
+        let (left, top) = overlay_origin(self.width, self.height, &self.overlay);
+        let (x, y) = (x - left, y - top);
+        self.overlay.rects.iter().enumerate().find_map(|(row, rects)| {
+            rects.iter().position(|r| r.contains(x, y)).map(|col| (row, col))
+        })
+    }
+
+    /// Shadertoy style `iMouse`: position while a button is held and where it
+    /// was pressed, negative once released.
+    pub fn set_mouse(&mut self, mouse: [f32; 4]) {
+        self.mouse = mouse;
+    }
+
+    /// Draw a frame `dt` milliseconds into the animation and show it.
+    pub fn render(&self, dt: u32) {
+        self.draw(dt);
 
         // By default, eglSwapBuffers blocks until we receive the next frame event.
         // This is undesirable since it makes it impossible to process other events
@@ -80,13 +331,125 @@ impl Renderer {
             .expect("unable to post the surface content");
     }
 
+    /// Draw a frame without presenting it.
+    pub fn draw(&self, dt: u32) {
+        self.make_current();
+
+        // iResolution and iMouse are in pixels of whatever the shader draws into
+        let (width, height) = self.target.as_ref().map_or((self.width, self.height), |t| (t.width, t.height));
+        let mouse_scale = width as f32 / self.width as f32;
+        let m = self.mouse.map(|v| v * mouse_scale);
+        unsafe {
+            if let Some(target) = &self.target {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, target.fbo);
+            }
+            gl::UseProgram(self.program);
+            gl::BindVertexArray(self.vao);
+            gl::Uniform4f(self.u_mouse, m[0], m[1], m[2], m[3]);
+        }
+        render(width, height, self.u_time, self.u_res, self.u_hms, dt);
+        if let Some(target) = &self.target {
+            unsafe {
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, target.fbo);
+                gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+                gl::BlitFramebuffer(
+                    0, 0, width, height,
+                    0, 0, self.width, self.height,
+                    gl::COLOR_BUFFER_BIT, gl::LINEAR
+                    );
+                gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            }
+        }
+        render_overlay(self.width, self.height, &self.overlay);
+    }
+
+    /// What was drawn last, as RGB rows from top to bottom.
+    pub fn read_pixels(&self) -> Vec<u8> {
+        self.make_current();
+        let stride = self.width as usize * 3;
+        let mut data = vec![0u8; stride * self.height as usize];
+        unsafe {
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(
+                0, 0, self.width, self.height,
+                gl::RGB, gl::UNSIGNED_BYTE, data.as_mut_ptr() as *mut GLvoid
+                );
+            check_gl_errors();
+        }
+        // OpenGL starts at the bottom row
+        data.chunks(stride).rev().flatten().copied().collect()
+    }
+
     pub fn resize(&mut self, width: i32, height: i32) {
-        self.wl_egl_surface.resize(width, height, 0, 0);
+        if let Some(wl_egl_surface) = &self.wl_egl_surface {
+            wl_egl_surface.resize(width, height, 0, 0);
+        }
         self.width = width;
         self.height = height;
+        if self.target.is_some() {
+            self.update_target();
+        }
     }
 }
 
+impl LockRenderer for Renderer {
+    fn set_overlay(&mut self, rows: Vec<Vec<Item>>) {
+        Renderer::set_overlay(self, rows)
+    }
+
+    fn render(&self, dt: u32) {
+        Renderer::render(self, dt)
+    }
+
+    fn size(&self) -> (i32, i32) {
+        Renderer::size(self)
+    }
+
+    fn resize(&mut self, width: i32, height: i32) {
+        Renderer::resize(self, width, height)
+    }
+
+    fn hit_test(&self, x: f64, y: f64) -> Option<(usize, usize)> {
+        Renderer::hit_test(self, x, y)
+    }
+
+    fn set_mouse(&mut self, mouse: [f32; 4]) {
+        Renderer::set_mouse(self, mouse)
+    }
+
+    fn set_scale(&mut self, scale: f32) {
+        Renderer::set_scale(self, scale)
+    }
+
+    fn set_shader(&mut self, shader: &str) -> bool {
+        Renderer::set_shader(self, shader)
+    }
+
+    fn set_render_scale(&mut self, scale: f32) {
+        Renderer::set_render_scale(self, scale)
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        // the display is shared, only release what this renderer created
+        if let Some(target) = self.target.take() {
+            self.make_current();
+            delete_target(&target);
+        }
+        self.egl.make_current(self.egl_display, None, None, None).ok();
+        self.egl.destroy_surface(self.egl_display, self.egl_surface).ok();
+        self.egl.destroy_context(self.egl_display, self.egl_context).ok();
+    }
+}
+
+fn create_instance() -> egl::Instance<egl::Static> {
+    let egl = egl::Instance::new(egl::Static);
+    egl.bind_api(egl::OPENGL_API).expect("unable to select OpenGL API");
+    gl::load_with(|name| egl.get_proc_address(name).unwrap() as *const std::ffi::c_void);
+    egl
+}
+
 fn setup_surface(egl: &egl::Instance<egl::Static>, surface: &wl_surface::WlSurface, width: i32, height: i32, egl_display: egl::Display, egl_config: egl::Config) -> (wayland_egl::WlEglSurface, egl::Surface) {
     let wl_egl_surface = wayland_egl::WlEglSurface::new(surface.id(), width, height).expect("Unable to init wl_egl_surface");
 
@@ -110,8 +473,11 @@ fn setup_egl(egl: &egl::Instance<egl::Static>, display: &wl_display::WlDisplay)
     egl_display
 }
 
-fn create_context(egl: &egl::Instance<egl::Static>, display: egl::Display) -> (egl::Context, egl::Config) {
+/// `surface_type` is `WINDOW_BIT` for Wayland surfaces, `PBUFFER_BIT` for offscreen ones.
+fn create_context(egl: &egl::Instance<egl::Static>, display: egl::Display, surface_type: egl::Int) -> (egl::Context, egl::Config) {
     let attributes = [
+        egl::SURFACE_TYPE,
+        surface_type,
         egl::RED_SIZE,
         8,
         egl::GREEN_SIZE,
@@ -166,28 +532,20 @@ fn check_gl_errors() {
     }
 }
 
-unsafe fn check_shader_status(shader: GLuint) {
+unsafe fn check_shader_status(shader: GLuint) -> Result<(), String> {
     let mut status = gl::FALSE as GLint;
     gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut status);
-    if status != (gl::TRUE as GLint) {
-        let mut len = 0;
-        gl::GetProgramiv(shader, gl::INFO_LOG_LENGTH, &mut len);
-        if len > 0 {
-            let mut buf = Vec::with_capacity(len as usize);
-            buf.set_len((len as usize) - 1); // subtract 1 to skip the trailing null character
-            gl::GetProgramInfoLog(
-                shader,
-                len,
-                ptr::null_mut(),
-                buf.as_mut_ptr() as *mut GLchar,
-                );
-
-            let log = String::from_utf8(buf).unwrap();
-            eprintln!("shader compilation log:\n{}", log);
-        }
-
-        panic!("shader compilation failed");
+    if status == (gl::TRUE as GLint) {
+        return Ok(());
     }
+
+    let mut len = 0;
+    gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
+    let mut buf = vec![0u8; len.max(1) as usize];
+    gl::GetShaderInfoLog(shader, len, ptr::null_mut(), buf.as_mut_ptr() as *mut GLchar);
+    // skip the trailing null character
+    buf.pop();
+    Err(String::from_utf8_lossy(&buf).into_owned())
 }
 
 const VERTEX: &'static [GLint; 8] = &[
@@ -207,6 +565,7 @@ in vec2 position;
 uniform float iTime;
 uniform vec3 iResolution;
 uniform float iDate;
+uniform vec4 iMouse;
 
 out vec2 fragPos;
 
@@ -217,16 +576,19 @@ void main() {
 }
 \0";
 
-const FRAGMENT_SHADER: &[u8] = b"#version 400
+const FRAGMENT_HEADER: &str = "#version 400
 in vec2 fragPos;
 
 uniform float iTime;
 uniform vec3 iResolution;
 uniform float iDate;
+uniform vec4 iMouse;
 
 out vec4 color;
+";
 
 /// Source: https://www.shadertoy.com/view/ll3yWj
+pub const DEFAULT_SHADER: &str = "
 void mainImage( out vec4 fragColor, in vec2 fragCoord )
 {
     // Normalized pixel coordinates (from 0 to 1)
@@ -238,30 +600,28 @@ void mainImage( out vec4 fragColor, in vec2 fragCoord )
     // Output to screen
     fragColor = vec4(col,1.0);
 }
+";
+
+const FRAGMENT_FOOTER: &str = "
 /// shader ends here
 void main() {
     color = vec4(1.0f);
     mainImage(color, fragPos);
 }
-\0";
+";
 
-fn compile_program() -> (GLint, GLint, GLint) {
+/// Build the shader program around a Shadertoy style `mainImage` function.
+fn compile_program(main_image: &str) -> Result<(GLuint, GLint, GLint, GLint, GLint), String> {
+    let source = format!("{}{}{}\0", FRAGMENT_HEADER, main_image, FRAGMENT_FOOTER);
     unsafe {
-        let vertex_shader = gl::CreateShader(gl::VERTEX_SHADER);
-        check_gl_errors();
-        let src = CStr::from_bytes_with_nul_unchecked(VERTEX_SHADER).as_ptr();
-        gl::ShaderSource(vertex_shader, 1, (&[src]).as_ptr(), ptr::null());
-        check_gl_errors();
-        gl::CompileShader(vertex_shader);
-        check_shader_status(vertex_shader);
-
-        let fragment_shader = gl::CreateShader(gl::FRAGMENT_SHADER);
-        check_gl_errors();
-        let src = CStr::from_bytes_with_nul_unchecked(FRAGMENT_SHADER).as_ptr();
-        gl::ShaderSource(fragment_shader, 1, (&[src]).as_ptr(), ptr::null());
-        check_gl_errors();
-        gl::CompileShader(fragment_shader);
-        check_shader_status(fragment_shader);
+        let vertex_shader = compile_shader(gl::VERTEX_SHADER, VERTEX_SHADER)?;
+        let fragment_shader = match compile_shader(gl::FRAGMENT_SHADER, source.as_bytes()) {
+            Ok(shader) => shader,
+            Err(log) => {
+                gl::DeleteShader(vertex_shader);
+                return Err(log);
+            }
+        };
 
         let program = gl::CreateProgram();
         check_gl_errors();
@@ -271,9 +631,24 @@ fn compile_program() -> (GLint, GLint, GLint) {
         check_gl_errors();
         gl::LinkProgram(program);
         check_gl_errors();
+        // owned by the program from here on
+        gl::DeleteShader(vertex_shader);
+        gl::DeleteShader(fragment_shader);
         gl::UseProgram(program);
         check_gl_errors();
 
+        let u_time = get_uniform_loc(program, "iTime");
+        let u_res = get_uniform_loc(program, "iResolution");
+        let u_hms = get_uniform_loc(program, "iDate");
+        let u_mouse = get_uniform_loc(program, "iMouse");
+
+        Ok((program, u_time, u_res, u_hms, u_mouse))
+    }
+}
+
+/// Full screen quad the shader is drawn on.
+fn create_quad() -> GLuint {
+    unsafe {
         let mut buffer = 0;
         gl::GenBuffers(1, &mut buffer);
         check_gl_errors();
@@ -312,11 +687,24 @@ fn compile_program() -> (GLint, GLint, GLint) {
             );
         check_gl_errors();
 
-        let u_time = get_uniform_loc(program, "iTime");
-        let u_res = get_uniform_loc(program, "iResolution");
-        let u_hms = get_uniform_loc(program, "iDate");
+        vertex_input
+    }
+}
 
-        (u_time, u_res, u_hms)
+/// Compile `source`, which has to end with a NUL byte, or return the compile log.
+unsafe fn compile_shader(kind: GLenum, source: &[u8]) -> Result<GLuint, String> {
+    let shader = gl::CreateShader(kind);
+    check_gl_errors();
+    let src = CStr::from_bytes_with_nul_unchecked(source).as_ptr();
+    gl::ShaderSource(shader, 1, (&[src]).as_ptr(), ptr::null());
+    check_gl_errors();
+    gl::CompileShader(shader);
+    match check_shader_status(shader) {
+        Ok(()) => Ok(shader),
+        Err(log) => {
+            gl::DeleteShader(shader);
+            Err(log)
+        }
     }
 }
 
@@ -345,3 +733,175 @@ fn render(width: i32, height: i32, u_time: GLint, u_res: GLint, u_hms: GLint, dt
         //check_gl_errors();
     }
 }
+
+const OVERLAY_VERTEX_SHADER: &[u8] = b"#version 400
+in vec2 position;
+
+// center and half size of the text quad in clip space
+uniform vec4 rect;
+
+out vec2 texPos;
+
+void main() {
+    gl_Position = vec4(rect.xy + position * rect.zw, 0.0f, 1.0f);
+    texPos = vec2(position.x * .5 + .5, .5 - position.y * .5);
+}
+\0";
+
+const OVERLAY_FRAGMENT_SHADER: &[u8] = b"#version 400
+in vec2 texPos;
+
+uniform sampler2D text;
+
+out vec4 color;
+
+void main() {
+    color = vec4(1.0f, 1.0f, 1.0f, texture(text, texPos).r);
+}
+\0";
+
+fn compile_overlay_program() -> (GLuint, GLuint, GLuint, GLint) {
+    unsafe {
+        let vertex_shader = compile_shader(gl::VERTEX_SHADER, OVERLAY_VERTEX_SHADER)
+            .unwrap_or_else(|log| panic!("overlay shader compilation failed:\n{}", log));
+        let fragment_shader = compile_shader(gl::FRAGMENT_SHADER, OVERLAY_FRAGMENT_SHADER)
+            .unwrap_or_else(|log| panic!("overlay shader compilation failed:\n{}", log));
+
+        let program = gl::CreateProgram();
+        check_gl_errors();
+        gl::AttachShader(program, vertex_shader);
+        check_gl_errors();
+        gl::AttachShader(program, fragment_shader);
+        check_gl_errors();
+        gl::LinkProgram(program);
+        check_gl_errors();
+
+        let mut vertex_input = 0;
+        gl::GenVertexArrays(1, &mut vertex_input);
+        check_gl_errors();
+        gl::BindVertexArray(vertex_input);
+        check_gl_errors();
+
+        let mut buffer = 0;
+        gl::GenBuffers(1, &mut buffer);
+        check_gl_errors();
+        gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+        check_gl_errors();
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            8 * 4,
+            VERTEX.as_ptr() as *const std::ffi::c_void,
+            gl::STATIC_DRAW
+            );
+        check_gl_errors();
+        gl::EnableVertexAttribArray(0);
+        check_gl_errors();
+        gl::VertexAttribPointer(
+            0, 2, gl::INT, gl::FALSE as GLboolean, 0, ptr::null()
+            );
+        check_gl_errors();
+
+        let mut indexes = 0;
+        gl::GenBuffers(1, &mut indexes);
+        check_gl_errors();
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, indexes);
+        check_gl_errors();
+        gl::BufferData(
+            gl::ELEMENT_ARRAY_BUFFER,
+            4 * 4,
+            INDEXES.as_ptr() as *const std::ffi::c_void,
+            gl::STATIC_DRAW
+            );
+        check_gl_errors();
+
+        let mut texture = 0;
+        gl::GenTextures(1, &mut texture);
+        check_gl_errors();
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        check_gl_errors();
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+        check_gl_errors();
+
+        let u_rect = get_uniform_loc(program, "rect");
+
+        (program, vertex_input, texture, u_rect)
+    }
+}
+
+/// Color texture and framebuffer of `width` by `height` pixels.
+fn create_target(width: i32, height: i32) -> Target {
+    unsafe {
+        let mut texture = 0;
+        gl::GenTextures(1, &mut texture);
+        check_gl_errors();
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        check_gl_errors();
+        gl::TexImage2D(
+            gl::TEXTURE_2D, 0, gl::RGBA8 as GLint,
+            width, height, 0,
+            gl::RGBA, gl::UNSIGNED_BYTE, ptr::null()
+            );
+        check_gl_errors();
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+        check_gl_errors();
+
+        let mut fbo = 0;
+        gl::GenFramebuffers(1, &mut fbo);
+        check_gl_errors();
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+        check_gl_errors();
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture, 0);
+        check_gl_errors();
+        if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+            panic!("offscreen framebuffer incomplete");
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        Target { fbo, texture, width, height }
+    }
+}
+
+fn delete_target(target: &Target) {
+    unsafe {
+        gl::DeleteFramebuffers(1, &target.fbo);
+        gl::DeleteTextures(1, &target.texture);
+    }
+}
+
+/// Top left corner of the overlay on the surface, in pixels. It is centered in
+/// the lower half, but moved up when too tall to fit there.
+fn overlay_origin(width: i32, height: i32, overlay: &Overlay) -> (f64, f64) {
+    let left = (width - overlay.width) as f64 / 2.;
+    let top = height as f64 * 0.75 - overlay.height as f64 / 2.;
+    (left, top.min((height - overlay.height) as f64).max(0.))
+}
+
+fn render_overlay(width: i32, height: i32, overlay: &Overlay) {
+    if overlay.width == 0 || overlay.height == 0 {
+        return;
+    }
+    // one texel per pixel
+    let half_w = overlay.width as f32 / width as f32;
+    let half_h = overlay.height as f32 / height as f32;
+    let (left, top) = overlay_origin(width, height, overlay);
+    let x = (left as f32 + overlay.width as f32 / 2.) / width as f32 * 2. - 1.;
+    let y = 1. - (top as f32 + overlay.height as f32 / 2.) / height as f32 * 2.;
+    unsafe {
+        // the shader may have been drawn at a lower resolution
+        gl::Viewport(0, 0, width, height);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+
+        gl::UseProgram(overlay.program);
+        gl::BindVertexArray(overlay.vao);
+        gl::BindTexture(gl::TEXTURE_2D, overlay.texture);
+        gl::Uniform4f(overlay.u_rect, x, y, half_w, half_h);
+
+        gl::DrawElements(gl::TRIANGLE_FAN, 4, gl::UNSIGNED_INT, std::ptr::null());
+        gl::Disable(gl::BLEND);
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    ffi::{CStr, CString},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+use calloop::channel::{self, Channel};
+
+/// Pause between a timed out check and the next one, so the error can be read.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+/// How often a check running in the helper process looks for cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Touched,
+    NoKey,
+    /// A key was found but not touched in time.
+    Timeout,
+}
+
+impl Outcome {
+    /// Exit status of `wlock-u2f-check` for the outcome.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Outcome::Touched => 0,
+            Outcome::NoKey => 1,
+            Outcome::Timeout => 2,
+        }
+    }
+
+    /// The outcome `wlock-u2f-check` exited with, `NoKey` if it failed.
+    fn from_exit_code(code: Option<i32>) -> Self {
+        match code {
+            Some(0) => Outcome::Touched,
+            Some(2) => Outcome::Timeout,
+            _ => Outcome::NoKey,
+        }
+    }
+}
+
+/// Waits for a touch on a security key of the user running wlock. Runs on its
+/// own thread, so blocking until the key is touched or the check times out is fine.
+pub trait SecurityKey: Send {
+    /// Should give up soon after `cancel` is set, the outcome is ignored then.
+    fn check(&mut self, cancel: &Cancel) -> Outcome;
+}
+
+/// Set once the result of a check is no longer wanted, because the lock ended.
+#[derive(Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A check started, the key can be touched now.
+    Waiting,
+    Done(Outcome),
+}
+
+/// Checks security keys with a PAM service using pam_u2f, configured with the
+/// `cue` option so a found key can be told apart from a missing one.
+///
+/// pam_u2f can not be interrupted while it waits for a touch, so every check
+/// runs in `wlock-u2f-check`, a helper process that is killed when the check
+/// is cancelled.
+pub struct PamU2f {
+    service: String,
+    helper: PathBuf,
+}
+
+impl PamU2f {
+    pub fn new(service: &str) -> Self {
+        PamU2f { service: service.to_string(), helper: PathBuf::from("wlock-u2f-check") }
+    }
+
+    /// Run `helper` instead of `wlock-u2f-check` from `PATH`.
+    pub fn helper(mut self, helper: &Path) -> Self {
+        self.helper = helper.to_path_buf();
+        self
+    }
+
+    /// Run the PAM check in this process, blocking until it is done. This is
+    /// what the helper does, everything else should use `check`.
+    pub fn authenticate(&self) -> Outcome {
+        let username = match users::get_current_username() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => {
+                log::warn!("Unable to determine current user");
+                return Outcome::NoKey;
+            }
+        };
+
+        let conversation = Cue { username, cued: false };
+        let mut authenticator = match pam::Authenticator::with_handler(&self.service, conversation) {
+            Ok(a) => a,
+            Err(e) => {
+                log::warn!("Unable to start PAM: {}", e);
+                return Outcome::NoKey;
+            }
+        };
+        match authenticator.authenticate() {
+            Ok(()) => Outcome::Touched,
+            Err(_) if authenticator.get_handler().cued => Outcome::Timeout,
+            Err(e) => {
+                log::warn!("Security key check failed: {}", e);
+                Outcome::NoKey
+            }
+        }
+    }
+}
+
+impl SecurityKey for PamU2f {
+    fn check(&mut self, cancel: &Cancel) -> Outcome {
+        let spawned = Command::new(&self.helper)
+            .arg(&self.service)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn();
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                log::warn!("Unable to run {}: {}", self.helper.display(), e);
+                return Outcome::NoKey;
+            }
+        };
+
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => return Outcome::from_exit_code(status.code()),
+                Ok(None) => {}
+                Err(e) => {
+                    log::warn!("Security key check failed: {}", e);
+                    return Outcome::NoKey;
+                }
+            }
+            if cancel.is_cancelled() {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Outcome::NoKey;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// PAM conversation without a password, noting whether pam_u2f asked for a touch.
+struct Cue {
+    username: String,
+    cued: bool,
+}
+
+impl pam::Converse for Cue {
+    fn prompt_echo(&mut self, _: &CStr) -> Result<CString, ()> {
+        Err(())
+    }
+
+    fn prompt_blind(&mut self, _: &CStr) -> Result<CString, ()> {
+        Err(())
+    }
+
+    fn info(&mut self, _: &CStr) {
+        self.cued = true;
+    }
+
+    fn error(&mut self, msg: &CStr) {
+        log::warn!("pam_u2f: {}", msg.to_string_lossy());
+    }
+
+    fn username(&self) -> &str {
+        &self.username
+    }
+}
+
+/// Controls the checks started by `spawn`, dropping it cancels the running one.
+pub struct Handle {
+    retries: mpsc::Sender<()>,
+    cancel: Cancel,
+}
+
+impl Handle {
+    /// Check again after no key was found, for when one was plugged in.
+    pub fn retry(&self) {
+        let _ = self.retries.send(());
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+/// Check `key` on a new thread until it is touched or the returned handle is
+/// dropped. Timeouts are retried right away, a missing key only after `Handle::retry`.
+pub fn spawn(mut key: Box<dyn SecurityKey>) -> (Handle, Channel<Event>) {
+    let (sender, channel) = channel::channel();
+    let (retries, retry) = mpsc::channel();
+    let cancel = Cancel::default();
+    let handle = Handle { retries, cancel: cancel.clone() };
+    thread::spawn(move || loop {
+        if sender.send(Event::Waiting).is_err() {
+            return;
+        }
+        let outcome = key.check(&cancel);
+        if cancel.is_cancelled() || sender.send(Event::Done(outcome)).is_err() {
+            return;
+        }
+        // retries asked for while checking are answered by this check
+        retry.try_iter().for_each(drop);
+        let next = match outcome {
+            Outcome::Touched => return,
+            Outcome::Timeout => match retry.recv_timeout(RETRY_DELAY) {
+                Err(mpsc::RecvTimeoutError::Disconnected) => Err(mpsc::RecvError),
+                _ => Ok(()),
+            },
+            Outcome::NoKey => retry.recv(),
+        };
+        if next.is_err() {
+            return;
+        }
+    });
+    (handle, channel)
+}
@@ -0,0 +1,358 @@
+use std::{fmt, time::Instant};
+
+use calloop::{channel, generic::Generic, ping::{make_ping, Ping, PingSource}, signals::{Signal, Signals}, EventLoop, Interest, Mode, PostAction};
+use wayland_client::{backend::WaylandError, protocol::{wl_display, wl_surface}, Connection, DispatchError, QueueHandle, WaylandSource};
+use wayland_protocols::ext::session_lock::v1::client::ext_session_lock_v1;
+
+use crate::{app_data::AppData, auth::{self, Authenticator}, config::Config, fprint::{self, Fprint}, ipc, logind::Logind, notify, renderer::{self, LockRenderer, NewRenderer}, security_key::{self, PamU2f, SecurityKey}, state::{LockState, UnlockReason}};
+
+/// What happened to a lock, passed to the callback set with `LockSession::on_event`.
+pub enum Event {
+    /// The compositor confirmed that the session is locked.
+    Locked,
+    /// A password was rejected, `attempts` counts all failures so far.
+    AttemptFailed { attempts: u32 },
+    /// The session was unlocked, the lock has ended. `failed_attempts` counts
+    /// the failures while it was locked.
+    Unlocked { failed_attempts: u32 },
+}
+
+/// Sets up fingerprint verification, called on another thread.
+type ConnectFprint = Box<dyn FnOnce() -> Result<Fprint, dbus::Error> + Send>;
+
+/// Why a lock could not be held.
+#[derive(Debug)]
+pub enum Error {
+    /// The compositor lacks a global needed for locking, named by its interface.
+    MissingGlobal(&'static str),
+    /// The compositor refused the lock or ended it without an unlock.
+    LockFinished,
+    Wayland(DispatchError),
+    EventLoop(calloop::Error),
+    /// A D-Bus service that was asked for could not be used.
+    DBus(dbus::Error),
+    /// The authenticator could never accept a password, locking would shut the user out.
+    Auth(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingGlobal(interface) => write!(f, "compositor does not support {}", interface),
+            Error::LockFinished => write!(f, "compositor refused or ended the lock"),
+            Error::Wayland(e) => write!(f, "wayland connection failed: {}", e),
+            Error::EventLoop(e) => write!(f, "event loop failed: {}", e),
+            Error::DBus(e) => write!(f, "D-Bus: {}", e),
+            Error::Auth(e) => write!(f, "refusing to lock, passwords can not be checked: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<DispatchError> for Error {
+    fn from(e: DispatchError) -> Self {
+        Error::Wayland(e)
+    }
+}
+
+impl From<WaylandError> for Error {
+    fn from(e: WaylandError) -> Self {
+        Error::Wayland(e.into())
+    }
+}
+
+impl From<calloop::Error> for Error {
+    fn from(e: calloop::Error) -> Self {
+        Error::EventLoop(e)
+    }
+}
+
+impl<T> From<calloop::InsertError<T>> for Error {
+    fn from(e: calloop::InsertError<T>) -> Self {
+        Error::EventLoop(e.error)
+    }
+}
+
+impl From<dbus::Error> for Error {
+    fn from(e: dbus::Error) -> Self {
+        Error::DBus(e)
+    }
+}
+
+/// Unlocks the session from anywhere, see `LockSession::unlock_handle`.
+#[derive(Clone)]
+pub struct UnlockHandle {
+    ping: Ping,
+}
+
+impl UnlockHandle {
+    pub fn unlock(&self) {
+        self.ping.ping();
+    }
+}
+
+/// A session lock on a Wayland connection, configured before calling `lock`.
+///
+/// By default passwords are checked with PAM and the lock screen is drawn with
+/// `Renderer`, both can be replaced to build a different locker on top.
+pub struct LockSession<'a> {
+    conn: &'a Connection,
+    config: Config,
+    authenticator: Box<dyn Authenticator>,
+    new_renderer: NewRenderer,
+    on_event: Option<Box<dyn FnMut(Event)>>,
+    unlock: Option<PingSource>,
+    logind: Option<Logind>,
+    connect_fprint: Option<ConnectFprint>,
+    security_key: Option<Box<dyn SecurityKey>>,
+    sleep_lock: bool,
+}
+
+impl<'a> LockSession<'a> {
+    pub fn new(conn: &'a Connection, config: Config) -> Self {
+        LockSession {
+            conn,
+            authenticator: config.authenticator(),
+            config,
+            new_renderer: Box::new(renderer::new_egl),
+            on_event: None,
+            unlock: None,
+            logind: None,
+            connect_fprint: None,
+            security_key: None,
+            sleep_lock: false,
+        }
+    }
+
+    /// Check passwords with `authenticator` instead of PAM.
+    pub fn authenticator(mut self, authenticator: impl Authenticator + 'static) -> Self {
+        self.authenticator = Box::new(authenticator);
+        self
+    }
+
+    /// Draw the lock screen with renderers made by `new_renderer`.
+    pub fn renderer(mut self, new_renderer: impl FnMut(&wl_display::WlDisplay, &wl_surface::WlSurface, (i32, i32), &Config) -> Box<dyn LockRenderer> + 'static) -> Self {
+        self.new_renderer = Box::new(new_renderer);
+        self
+    }
+
+    /// Call `on_event` whenever the state of the lock changes.
+    pub fn on_event(mut self, on_event: impl FnMut(Event) + 'static) -> Self {
+        self.on_event = Some(Box::new(on_event));
+        self
+    }
+
+    /// Also unlock when `key` reports a touch, instead of checking with
+    /// pam_u2f as `security-key` does.
+    pub fn security_key(mut self, key: impl SecurityKey + 'static) -> Self {
+        self.security_key = Some(Box::new(key));
+        self
+    }
+
+    /// A handle that ends the lock without a password, usable from other threads.
+    pub fn unlock_handle(&mut self) -> Result<UnlockHandle, Error> {
+        let (ping, source) = make_ping().map_err(calloop::Error::from)?;
+        self.unlock = Some(source);
+        Ok(UnlockHandle { ping })
+    }
+
+    /// Use the daemon's logind connection while locked. `sleep_lock` is set
+    /// when locking because the system is about to sleep.
+    pub(crate) fn logind(mut self, logind: Option<Logind>, sleep_lock: bool) -> Self {
+        self.logind = logind;
+        self.sleep_lock = sleep_lock;
+        self
+    }
+
+    /// Verify fingerprints with the reader `connect` sets up instead of
+    /// connecting to fprintd on the system bus.
+    pub(crate) fn fingerprint(mut self, connect: impl FnOnce() -> Result<Fprint, dbus::Error> + Send + 'static) -> Self {
+        self.connect_fprint = Some(Box::new(connect));
+        self
+    }
+
+    /// Lock the session and block until it is unlocked again. If this fails
+    /// after the compositor confirmed the lock, the session stays locked.
+    pub fn lock(self) -> Result<(), Error> {
+        self.run().1
+    }
+
+    /// Like `lock`, handing the logind connection back afterwards, also when
+    /// locking failed.
+    pub(crate) fn run(mut self) -> (Option<Logind>, Result<(), Error>) {
+        let mut app_data = AppData::new(self.config.clone(), self.logind.take(), self.sleep_lock);
+        let result = self.lock_with(&mut app_data);
+        (app_data.logind.take(), result)
+    }
+
+    fn lock_with(self, app_data: &mut AppData) -> Result<(), Error> {
+        let LockSession { conn, config, authenticator, new_renderer, on_event, unlock, logind: _, connect_fprint, security_key, sleep_lock: _ } = self;
+        authenticator.ready().map_err(Error::Auth)?;
+
+        // blocked right away, dying between locking and the event loop would leave
+        // the compositor locked without a client
+        let signals = Signals::new(&[Signal::SIGUSR1, Signal::SIGHUP, Signal::SIGTERM, Signal::SIGINT])?;
+
+        let display = conn.display();
+
+        let mut event_queue = conn.new_event_queue();
+        let qh = event_queue.handle();
+
+        let _registry = display.get_registry(&qh, ());
+
+        let (worker, auth_results) = auth::Worker::spawn(authenticator);
+        app_data.authenticator = Some(worker);
+        app_data.new_renderer = new_renderer;
+        app_data.on_event = on_event;
+        event_queue.roundtrip(app_data)?;
+
+        if app_data.compositor.is_none() {
+            return Err(Error::MissingGlobal("wl_compositor"));
+        }
+        if app_data.seat.is_none() {
+            return Err(Error::MissingGlobal("wl_seat"));
+        }
+        if app_data.shm.is_none() {
+            return Err(Error::MissingGlobal("wl_shm"));
+        }
+        let lock = match &app_data.lock_mgr {
+            Some(lock_mgr) => lock_mgr.lock(&qh, ()),
+            None => return Err(Error::MissingGlobal("ext_session_lock_manager_v1")),
+        };
+        app_data.transition(LockState::LockRequested);
+        event_queue.roundtrip(app_data)?;
+        event_queue.flush()?;
+        if app_data.lifecycle.get() == LockState::Failed {
+            return Err(Error::LockFinished);
+        }
+
+        create_surfaces(app_data, &qh, &lock);
+        event_queue.roundtrip(app_data)?;
+
+        app_data.render_and_schedule(&qh);
+
+        let mut event_loop: EventLoop<AppData> = EventLoop::try_new()?;
+        let handle = event_loop.handle();
+
+        WaylandSource::new(event_queue)?.insert(handle.clone())?;
+
+        handle.insert_source(signals, |event, _, app_data| app_data.handle_signal(event.signal()))?;
+
+        handle.insert_source(auth_results, |event, _, app_data| {
+            if let channel::Event::Msg(success) = event {
+                app_data.password_checked(success);
+            }
+        })?;
+
+        if let Some(source) = unlock {
+            handle.insert_source(source, |_, _, app_data| {
+                log::info!("Unlock requested by the embedding program");
+                app_data.unlock(UnlockReason::Trusted);
+            })?;
+        }
+
+        if let Some(fd) = app_data.logind.as_ref().map(Logind::fd) {
+            handle.insert_source(Generic::new(fd, Interest::READ, Mode::Level), |_, _, app_data| {
+                app_data.process_logind();
+                Ok(PostAction::Continue)
+            })?;
+        }
+
+        let connect_fprint = connect_fprint.or_else(|| {
+            config.fingerprint.then(|| Box::new(Fprint::connect) as ConnectFprint)
+        });
+        if let Some(connect) = connect_fprint {
+            // every call to fprintd blocks, so the reader is claimed and
+            // watched on its own thread
+            let (fprint, fprint_events) = fprint::spawn(connect);
+            let mut fprint = Some(fprint);
+            handle.insert_source(fprint_events, move |event, _, app_data| {
+                if let channel::Event::Msg(event) = event {
+                    if let fprint::Event::Ready = event {
+                        app_data.fprint = fprint.take();
+                    }
+                    app_data.handle_fprint(event);
+                }
+            })?;
+        }
+
+        let security_key = security_key.or_else(|| {
+            config.security_key.then(|| Box::new(PamU2f::new(&config.u2f_service).helper(&config.u2f_helper)) as Box<dyn SecurityKey>)
+        });
+        if let Some(key) = security_key {
+            let (key_handle, key_events) = security_key::spawn(key);
+            app_data.security_key = Some(key_handle);
+            handle.insert_source(key_events, |event, _, app_data| {
+                if let channel::Event::Msg(event) = event {
+                    app_data.handle_security_key(event);
+                }
+            })?;
+        }
+
+        if let Some(path) = &config.socket {
+            if let Err(e) = ipc::Server::bind(path).and_then(|server| server.insert(&handle, AppData::handle_request)) {
+                log::warn!("Unable to open control socket {}: {}", path.display(), e);
+            }
+        }
+
+        //println!("Sleeping...");
+        //thread::sleep(Duration::from_millis(4000));
+
+        // also waits for the compositor to confirm the lock
+        while !matches!(app_data.lifecycle.get(), LockState::Unlocking | LockState::Failed) {
+            let timeout = app_data.next_timeout().map(|t| t.saturating_duration_since(Instant::now()));
+            if let Err(e) = event_loop.dispatch(timeout, app_data) {
+                // exiting without unlocking keeps the session locked
+                app_data.transition(LockState::Failed);
+                return Err(e.into());
+            }
+            app_data.handle_timers(&qh);
+        }
+        if app_data.lifecycle.get() == LockState::Failed {
+            return Err(Error::LockFinished);
+        }
+
+        lock.unlock_and_destroy();
+        conn.roundtrip()?;
+        // closes the control socket and restores the signal mask, only now
+        // that the compositor knows about the unlock
+        drop(event_loop);
+        app_data.transition(LockState::Done);
+        if let Some(logind) = &mut app_data.logind {
+            logind.unlocked();
+        }
+        log::info!("Successfully unlocked!");
+        let failed_attempts = app_data.failed_attempts;
+        app_data.emit(Event::Unlocked { failed_attempts });
+        // the lock screen is gone, so tell the user what happened while away
+        match failed_attempts {
+            0 => {}
+            1 => notify::send("Failed unlock attempt", "There was 1 failed attempt while locked"),
+            n => notify::send("Failed unlock attempts", &format!("There were {} failed attempts while locked", n)),
+        }
+        Ok(())
+    }
+}
+
+fn create_surfaces(app_data: &mut AppData, qh: &QueueHandle<AppData>, lock: &ext_session_lock_v1::ExtSessionLockV1) {
+    for mut s in &mut app_data.surfaces {
+        let surf = app_data.compositor.as_ref().unwrap().create_surface(qh, ());
+        //let child = app_data.compositor.as_ref().unwrap().create_surface(qh, ());
+        //let subsurface = app_data.subcompositor.as_ref().unwrap().get_subsurface(&child, &surf, qh, ());
+        //subsurface.set_sync();
+        let lock_surf = lock.get_lock_surface(&surf, &s.output, qh, ());
+        // correct size will be reset in lock surface handler
+        //let surface = WlEglSurface::new(surf.id(), 1, 1).unwrap();
+        //let child = WlEglSurface::new(child.id(), 1, 1).unwrap();
+        s.surface = Some(surf);
+        //s.child = Some(child);
+        //s.subsurface = Some(subsurface);
+        s.lock_surface = Some(lock_surf);
+        // without a viewport the buffer can only be scaled by whole numbers
+        if let (Some(fractional_scale_mgr), Some(viewporter)) = (&app_data.fractional_scale_mgr, &app_data.viewporter) {
+            s.fractional_scale = Some(fractional_scale_mgr.get_fractional_scale(s.surface.as_ref().unwrap(), qh, ()));
+            s.viewport = Some(viewporter.get_viewport(s.surface.as_ref().unwrap(), qh, ()));
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+
+/// Where a lock is in its lifecycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockState {
+    /// Binding globals, nothing is locked yet.
+    Connecting,
+    /// The lock was requested, the compositor has not confirmed it yet.
+    LockRequested,
+    Locked,
+    /// A password is being checked.
+    Authenticating,
+    /// The lock is being released, only reachable with an `Unlock`.
+    Unlocking,
+    /// The compositor has been told to unlock.
+    Done,
+    /// The compositor refused the lock or the client can not go on. Ending
+    /// the process from here leaves the session locked.
+    Failed,
+}
+
+impl LockState {
+    /// Whether going from `self` to `next` is allowed. `Unlocking` never is,
+    /// it takes an `Unlock` through `Lifecycle::unlock`.
+    pub fn can_become(self, next: LockState) -> bool {
+        use LockState::*;
+        match (self, next) {
+            (Done | Failed, _) => false,
+            (_, Failed) => true,
+            (Connecting, LockRequested)
+                | (LockRequested, Locked)
+                | (Locked, Authenticating)
+                | (Authenticating, Locked)
+                | (Unlocking, Done) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether `unlock` allows releasing the lock from `self`. A password has
+    /// to have been checked in `Authenticating`, anything else needs the lock
+    /// to be confirmed.
+    pub fn can_unlock(self, unlock: &Unlock) -> bool {
+        match unlock.reason {
+            UnlockReason::Password => self == LockState::Authenticating,
+            UnlockReason::OtherFactor | UnlockReason::Trusted => self.is_locked(),
+        }
+    }
+
+    /// Whether the compositor confirmed the lock and it has not been released.
+    pub fn is_locked(self) -> bool {
+        matches!(self, LockState::Locked | LockState::Authenticating)
+    }
+
+    /// Name of the state as reported on the control socket.
+    pub fn name(self) -> &'static str {
+        match self {
+            LockState::Connecting => "connecting",
+            LockState::LockRequested => "lock-requested",
+            LockState::Locked => "locked",
+            LockState::Authenticating => "authenticating",
+            LockState::Unlocking => "unlocking",
+            LockState::Done => "done",
+            LockState::Failed => "failed",
+        }
+    }
+}
+
+/// Why a lock may be released.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnlockReason {
+    /// The typed password checked out.
+    Password,
+    /// A fingerprint or a security key checked out.
+    OtherFactor,
+    /// A source that needs no authentication asked: a signal, the control
+    /// socket, logind, an `UnlockHandle` or the grace period.
+    Trusted,
+}
+
+/// Permission to release the lock. Only handed out by wlock itself once the
+/// reason holds, so `Unlocking` can not be reached by accident.
+#[derive(Debug)]
+pub struct Unlock {
+    reason: UnlockReason,
+}
+
+impl Unlock {
+    pub(crate) fn new(reason: UnlockReason) -> Self {
+        Unlock { reason }
+    }
+
+    pub fn reason(&self) -> UnlockReason {
+        self.reason
+    }
+}
+
+#[derive(Debug)]
+pub struct IllegalTransition {
+    pub from: LockState,
+    pub to: LockState,
+}
+
+impl fmt::Display for IllegalTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "illegal lock state transition {:?} -> {:?}", self.from, self.to)
+    }
+}
+
+/// The current `LockState`, only changed through checked transitions.
+pub struct Lifecycle {
+    state: LockState,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Lifecycle { state: LockState::Connecting }
+    }
+
+    pub fn get(&self) -> LockState {
+        self.state
+    }
+
+    /// Move to `next` if allowed from the current state, logging the change.
+    pub fn transition(&mut self, next: LockState) -> Result<(), IllegalTransition> {
+        if !self.state.can_become(next) {
+            return Err(IllegalTransition { from: self.state, to: next });
+        }
+        log::debug!("Lock state: {:?} -> {:?}", self.state, next);
+        self.state = next;
+        Ok(())
+    }
+
+    /// Move to `Unlocking` if `unlock` allows it from the current state.
+    pub fn unlock(&mut self, unlock: Unlock) -> Result<(), IllegalTransition> {
+        if !self.state.can_unlock(&unlock) {
+            return Err(IllegalTransition { from: self.state, to: LockState::Unlocking });
+        }
+        log::debug!("Lock state: {:?} -> {:?} ({:?})", self.state, LockState::Unlocking, unlock.reason);
+        self.state = LockState::Unlocking;
+        Ok(())
+    }
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,65 @@
+use std::fs;
+
+use crate::auth::{Authenticator, ShadowFile, Stub};
+
+fn current_user() -> String {
+    users::get_current_username().unwrap().to_string_lossy().into_owned()
+}
+
+/// A shadow file with `hash` for the current user between two other users.
+fn shadow_file(hash: &str) -> tempfile::NamedTempFile {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let other = pwhash::sha512_crypt::hash("other").unwrap();
+    let contents = format!(
+        "wlock-other:{}:19000:0:99999:7:::\n{}:{}:19000:0:99999:7:::\nwlock-nobody:*:19000:0:99999:7:::\n",
+        other, current_user(), hash,
+    );
+    fs::write(file.path(), contents).unwrap();
+    file
+}
+
+#[test]
+fn shadow_accepts_password() {
+    let file = shadow_file(&pwhash::sha512_crypt::hash("hunter2").unwrap());
+    let shadow = ShadowFile::new(file.path());
+    assert_eq!(shadow.verify(&current_user(), "hunter2"), Ok(true));
+    assert_eq!(shadow.verify(&current_user(), "hunter3"), Ok(false));
+    assert_eq!(shadow.verify(&current_user(), "other"), Ok(false));
+}
+
+#[test]
+fn shadow_rejects_locked_account() {
+    let hash = pwhash::sha512_crypt::hash("hunter2").unwrap();
+    let file = shadow_file(&format!("!{}", hash));
+    assert!(ShadowFile::new(file.path()).verify(&current_user(), "hunter2").is_err());
+
+    let file = shadow_file("");
+    assert!(ShadowFile::new(file.path()).verify(&current_user(), "").is_err());
+}
+
+#[test]
+fn shadow_rejects_missing_file() {
+    let dir = tempfile::tempdir().unwrap();
+    assert!(ShadowFile::new(&dir.path().join("shadow")).verify(&current_user(), "").is_err());
+}
+
+#[test]
+fn shadow_needs_usable_hash() {
+    let file = shadow_file(&pwhash::sha512_crypt::hash("hunter2").unwrap());
+    assert!(ShadowFile::new(file.path()).usable_hash(&current_user()).is_ok());
+
+    // yescrypt, the default on current distributions
+    let file = shadow_file("$y$j9T$F5Jx5fExrKuPp53xLKQ..1$X3DX6M94c7o.9agCG9G317fhZg9SqC.5i5rd.RhAtQ7");
+    assert!(ShadowFile::new(file.path()).usable_hash(&current_user()).is_err());
+
+    let file = shadow_file("!");
+    assert!(ShadowFile::new(file.path()).usable_hash(&current_user()).is_err());
+}
+
+#[test]
+fn stub_accepts_only_its_password() {
+    let mut stub = Stub::new("x");
+    assert!(stub.check("x"));
+    assert!(!stub.check("xx"));
+    assert!(!stub.check(""));
+}
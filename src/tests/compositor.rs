@@ -0,0 +1,340 @@
+use std::{
+    io::{Seek, SeekFrom, Write},
+    os::unix::{io::AsRawFd, net::UnixStream},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use wayland_protocols::ext::session_lock::v1::server::{ext_session_lock_manager_v1, ext_session_lock_v1, ext_session_lock_surface_v1};
+use wayland_server::{
+    backend::ClientData,
+    protocol::{wl_callback, wl_compositor, wl_keyboard, wl_output, wl_seat, wl_shm, wl_surface},
+    Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, New,
+};
+use xkbcommon::xkb;
+
+/// How long `Compositor::run_until` waits before giving up.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Evdev keycode of the Enter key.
+pub const KEY_ENTER: u32 = 28;
+
+/// What the compositor does when a client asks for the lock.
+#[derive(Clone, Copy, PartialEq)]
+pub enum LockReply {
+    Locked,
+    Finished,
+}
+
+/// Everything the client did, for the tests to look at.
+pub struct State {
+    pub reply: LockReply,
+    pub outputs: Vec<wl_output::WlOutput>,
+    pub keyboards: Vec<wl_keyboard::WlKeyboard>,
+    pub surfaces: Vec<wl_surface::WlSurface>,
+    pub lock: Option<ext_session_lock_v1::ExtSessionLockV1>,
+    /// Lock surfaces and the output they were created for.
+    pub lock_surfaces: Vec<(ext_session_lock_surface_v1::ExtSessionLockSurfaceV1, wl_output::WlOutput)>,
+    /// Set when the client sent `unlock_and_destroy`.
+    pub unlocked: bool,
+    /// Set when the client destroyed the lock without unlocking.
+    pub lock_destroyed: bool,
+    serial: u32,
+}
+
+impl State {
+    fn next_serial(&mut self) -> u32 {
+        self.serial += 1;
+        self.serial
+    }
+}
+
+/// Mock compositor implementing just enough for wlock, driven from the test thread.
+pub struct Compositor {
+    display: Display<State>,
+    pub state: State,
+}
+
+impl Compositor {
+    /// Start a compositor with `outputs` outputs and a keyboard seat, returning
+    /// the socket for the client end.
+    pub fn new(outputs: u32, reply: LockReply) -> (Self, UnixStream) {
+        let mut display = Display::<State>::new().expect("unable to create display");
+        let dh = display.handle();
+        dh.create_global::<State, wl_compositor::WlCompositor, ()>(4, ());
+        dh.create_global::<State, wl_shm::WlShm, ()>(1, ());
+        dh.create_global::<State, wl_seat::WlSeat, ()>(5, ());
+        dh.create_global::<State, ext_session_lock_manager_v1::ExtSessionLockManagerV1, ()>(1, ());
+        for i in 0..outputs {
+            dh.create_global::<State, wl_output::WlOutput, u32>(2, i);
+        }
+
+        let (server, client) = UnixStream::pair().expect("unable to create socket pair");
+        display.insert_client(server, Arc::new(ClientState)).expect("unable to add client");
+
+        let state = State {
+            reply,
+            outputs: vec![],
+            keyboards: vec![],
+            surfaces: vec![],
+            lock: None,
+            lock_surfaces: vec![],
+            unlocked: false,
+            lock_destroyed: false,
+            serial: 0,
+        };
+        (Compositor { display, state }, client)
+    }
+
+    /// Process client requests until `done` returns true, panics after `TIMEOUT`.
+    pub fn run_until(&mut self, mut done: impl FnMut(&State) -> bool) {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            self.dispatch();
+            if done(&self.state) {
+                return;
+            }
+            if Instant::now() > deadline {
+                panic!("timed out waiting for the client");
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Process client requests for `duration`, for checking that something does not happen.
+    pub fn run_for(&mut self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        while Instant::now() < deadline {
+            self.dispatch();
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn dispatch(&mut self) {
+        self.display.dispatch_clients(&mut self.state).expect("unable to dispatch clients");
+        let _ = self.display.flush_clients();
+    }
+
+    /// Press and release the evdev key `key` on every keyboard.
+    pub fn key(&mut self, key: u32) {
+        for pressed in [wl_keyboard::KeyState::Pressed, wl_keyboard::KeyState::Released] {
+            let serial = self.state.next_serial();
+            for keyboard in &self.state.keyboards {
+                keyboard.key(serial, 0, key, pressed);
+            }
+        }
+        self.dispatch();
+    }
+
+    /// Give keyboard focus to the first lock surface.
+    pub fn focus(&mut self) {
+        let serial = self.state.next_serial();
+        let surface = match self.state.surfaces.first() {
+            Some(s) => s.clone(),
+            None => return,
+        };
+        for keyboard in &self.state.keyboards {
+            keyboard.enter(serial, &surface, vec![]);
+            keyboard.modifiers(serial, 0, 0, 0, 0);
+        }
+        self.dispatch();
+    }
+}
+
+struct ClientState;
+
+impl ClientData for ClientState {}
+
+impl GlobalDispatch<wl_compositor::WlCompositor, ()> for State {
+    fn bind(_: &mut Self, _: &DisplayHandle, _: &Client, resource: New<wl_compositor::WlCompositor>, _: &(), data_init: &mut DataInit<'_, Self>) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<wl_compositor::WlCompositor, ()> for State {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &wl_compositor::WlCompositor,
+        request: wl_compositor::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+        ) {
+        if let wl_compositor::Request::CreateSurface { id } = request {
+            state.surfaces.push(data_init.init(id, ()));
+        }
+    }
+}
+
+impl Dispatch<wl_surface::WlSurface, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &wl_surface::WlSurface,
+        request: wl_surface::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+        ) {
+        // frames are never presented, so callbacks are never done
+        if let wl_surface::Request::Frame { callback } = request {
+            data_init.init(callback, ());
+        }
+    }
+}
+
+impl Dispatch<wl_callback::WlCallback, ()> for State {
+    fn request(_: &mut Self, _: &Client, _: &wl_callback::WlCallback, _: wl_callback::Request, _: &(), _: &DisplayHandle, _: &mut DataInit<'_, Self>) {
+        // no request
+    }
+}
+
+impl GlobalDispatch<wl_shm::WlShm, ()> for State {
+    fn bind(_: &mut Self, _: &DisplayHandle, _: &Client, resource: New<wl_shm::WlShm>, _: &(), data_init: &mut DataInit<'_, Self>) {
+        let shm = data_init.init(resource, ());
+        shm.format(wl_shm::Format::Argb8888);
+    }
+}
+
+impl Dispatch<wl_shm::WlShm, ()> for State {
+    fn request(_: &mut Self, _: &Client, _: &wl_shm::WlShm, _: wl_shm::Request, _: &(), _: &DisplayHandle, _: &mut DataInit<'_, Self>) {
+        // only used for the cursor, which needs a pointer
+    }
+}
+
+impl GlobalDispatch<wl_seat::WlSeat, ()> for State {
+    fn bind(_: &mut Self, _: &DisplayHandle, _: &Client, resource: New<wl_seat::WlSeat>, _: &(), data_init: &mut DataInit<'_, Self>) {
+        let seat = data_init.init(resource, ());
+        seat.capabilities(wl_seat::Capability::Keyboard);
+    }
+}
+
+impl Dispatch<wl_seat::WlSeat, ()> for State {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &wl_seat::WlSeat,
+        request: wl_seat::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+        ) {
+        if let wl_seat::Request::GetKeyboard { id } = request {
+            let keyboard = data_init.init(id, ());
+            send_keymap(&keyboard);
+            // no repeats, tests press keys one at a time
+            keyboard.repeat_info(0, 0);
+            state.keyboards.push(keyboard);
+        }
+    }
+}
+
+impl Dispatch<wl_keyboard::WlKeyboard, ()> for State {
+    fn request(_: &mut Self, _: &Client, _: &wl_keyboard::WlKeyboard, _: wl_keyboard::Request, _: &(), _: &DisplayHandle, _: &mut DataInit<'_, Self>) {
+        // only release
+    }
+}
+
+/// Send a US layout, which the evdev keycodes in the tests assume.
+fn send_keymap(keyboard: &wl_keyboard::WlKeyboard) {
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = xkb::Keymap::new_from_names(&context, "evdev", "pc105", "us", "", None, xkb::KEYMAP_COMPILE_NO_FLAGS)
+        .expect("unable to compile keymap");
+    let mut text = keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1).into_bytes();
+    text.push(0);
+
+    let mut file = tempfile::tempfile().expect("unable to create keymap file");
+    file.write_all(&text).expect("unable to write keymap");
+    // the client reads from the shared offset
+    file.seek(SeekFrom::Start(0)).expect("unable to rewind keymap");
+    keyboard.keymap(wl_keyboard::KeymapFormat::XkbV1, file.as_raw_fd(), text.len() as u32);
+}
+
+impl GlobalDispatch<wl_output::WlOutput, u32> for State {
+    fn bind(state: &mut Self, _: &DisplayHandle, _: &Client, resource: New<wl_output::WlOutput>, _: &u32, data_init: &mut DataInit<'_, Self>) {
+        let output = data_init.init(resource, ());
+        output.scale(1);
+        output.done();
+        state.outputs.push(output);
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, ()> for State {
+    fn request(_: &mut Self, _: &Client, _: &wl_output::WlOutput, _: wl_output::Request, _: &(), _: &DisplayHandle, _: &mut DataInit<'_, Self>) {
+        // only release
+    }
+}
+
+impl GlobalDispatch<ext_session_lock_manager_v1::ExtSessionLockManagerV1, ()> for State {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ext_session_lock_manager_v1::ExtSessionLockManagerV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+        ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ext_session_lock_manager_v1::ExtSessionLockManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &ext_session_lock_manager_v1::ExtSessionLockManagerV1,
+        request: ext_session_lock_manager_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+        ) {
+        if let ext_session_lock_manager_v1::Request::Lock { id } = request {
+            let lock = data_init.init(id, ());
+            match state.reply {
+                LockReply::Locked => lock.locked(),
+                LockReply::Finished => lock.finished(),
+            }
+            state.lock = Some(lock);
+        }
+    }
+}
+
+impl Dispatch<ext_session_lock_v1::ExtSessionLockV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &ext_session_lock_v1::ExtSessionLockV1,
+        request: ext_session_lock_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+        ) {
+        match request {
+            ext_session_lock_v1::Request::GetLockSurface { id, surface: _, output } => {
+                let lock_surface = data_init.init(id, ());
+                let serial = state.next_serial();
+                lock_surface.configure(serial, 1920, 1080);
+                state.lock_surfaces.push((lock_surface, output));
+            }
+            ext_session_lock_v1::Request::UnlockAndDestroy => state.unlocked = true,
+            ext_session_lock_v1::Request::Destroy => state.lock_destroyed = true,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ext_session_lock_surface_v1::ExtSessionLockSurfaceV1, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &ext_session_lock_surface_v1::ExtSessionLockSurfaceV1,
+        _: ext_session_lock_surface_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+        ) {
+        // acks and destroy, nothing to check
+    }
+}
@@ -0,0 +1,32 @@
+use std::time::Duration;
+
+use crate::{app_data::AppData, config::Config};
+
+#[test]
+fn durations_must_fit() {
+    let mut config = Config::default();
+    assert!(config.set("grace", "1.5").is_ok());
+    assert_eq!(config.grace, Duration::from_millis(1500));
+    for value in ["-1", "nan", "inf", "1e30"] {
+        assert!(config.set("grace", value).is_err(), "{}", value);
+    }
+    assert_eq!(config.grace, Duration::from_millis(1500));
+}
+
+#[test]
+fn bad_argument_is_an_error() {
+    assert!(Config::load_with(None, ["--no-such-option".to_string()]).is_err());
+    assert!(Config::load_with(None, ["--fail-delay=inf".to_string()]).is_err());
+}
+
+#[test]
+fn reload_keeps_config_on_error() {
+    let mut app_data = AppData::new(Config { fail_hook_after: 7, ..Config::default() }, None, false);
+    // built in code, nothing to read again
+    app_data.reload();
+    assert_eq!(app_data.config.fail_hook_after, 7);
+
+    app_data.config.args = Some(vec!["--no-such-option".to_string()]);
+    app_data.reload();
+    assert_eq!(app_data.config.fail_hook_after, 7);
+}
@@ -0,0 +1,18 @@
+use crate::cursor::argb8888;
+
+/// An xcursor file with a single 1x1 image of `pixel`, an ARGB word.
+fn xcursor_file(pixel: u32) -> Vec<u8> {
+    const IMAGE: u32 = 0xfffd_0002;
+    let header = [u32::from_le_bytes(*b"Xcur"), 16, 0x1_0000, 1];
+    let toc = [IMAGE, 1, 28];
+    // header size, type, size, version, width, height, xhot, yhot, delay
+    let image = [36, IMAGE, 1, 1, 1, 1, 0, 0, 0, pixel];
+    header.iter().chain(&toc).chain(&image).flat_map(|w| w.to_le_bytes()).collect()
+}
+
+#[test]
+fn cursor_pixels_are_argb8888() {
+    let images = xcursor::parser::parse_xcursor(&xcursor_file(0xff11_2233)).unwrap();
+    // little endian ARGB, as in memory on a wl_shm buffer
+    assert_eq!(argb8888(&images[0]), [0x33, 0x22, 0x11, 0xff]);
+}
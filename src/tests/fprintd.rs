@@ -0,0 +1,99 @@
+use std::{
+    cell::Cell,
+    io::{BufRead, BufReader},
+    process::{Child, Command, Stdio},
+    rc::Rc,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use dbus::{
+    blocking::LocalConnection,
+    channel::{Channel, Sender},
+    message::MatchRule,
+    Message, Path,
+};
+
+const FPRINTD: &str = "net.reactivated.Fprint";
+const DEVICE_PATH: &str = "/net/reactivated/Fprint/Device/0";
+const DEVICE_IFACE: &str = "net.reactivated.Fprint.Device";
+/// How long the mock waits for the reader to be released.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A private bus, so the mock never meets the real fprintd.
+pub struct Bus {
+    daemon: Child,
+    pub address: String,
+}
+
+impl Bus {
+    /// Start the bus, `None` if there is no dbus-daemon to run it with.
+    pub fn new() -> Option<Self> {
+        let spawned = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .spawn();
+        let mut daemon = match spawned {
+            Ok(daemon) => daemon,
+            Err(e) => {
+                eprintln!("skipping, unable to start dbus-daemon: {}", e);
+                return None;
+            }
+        };
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+        Some(Bus { daemon, address: address.trim_end().to_string() })
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+/// Serve one reader on `bus` that answers the n-th `VerifyStart` with the
+/// n-th of `results`, until the reader is released.
+pub fn spawn(bus: &Bus, results: &[&'static str]) -> JoinHandle<()> {
+    let address = bus.address.clone();
+    let mut results = results.to_vec().into_iter();
+    let (ready_tx, ready) = mpsc::channel();
+    let mock = thread::spawn(move || {
+        let mut channel = Channel::open_private(&address).unwrap();
+        channel.register().unwrap();
+        let conn = LocalConnection::from(channel);
+        conn.request_name(FPRINTD, false, true, true).unwrap();
+
+        let released = Rc::new(Cell::new(false));
+        let r = released.clone();
+        conn.start_receive(MatchRule::new_method_call(), Box::new(move |call, conn| {
+            let member = call.member().map(|m| m.to_string()).unwrap_or_default();
+            let reply = match member.as_str() {
+                "GetDefaultDevice" => call.method_return().append1(Path::from(DEVICE_PATH)),
+                _ => call.method_return(),
+            };
+            conn.send(reply).unwrap();
+            match member.as_str() {
+                "VerifyStart" => if let Some(result) = results.next() {
+                    let status = Message::new_signal(DEVICE_PATH, DEVICE_IFACE, "VerifyStatus").unwrap()
+                        .append2(result, true);
+                    conn.send(status).unwrap();
+                },
+                "Release" => r.set(true),
+                _ => {}
+            }
+            true
+        }));
+        ready_tx.send(()).unwrap();
+
+        let deadline = Instant::now() + TIMEOUT;
+        while !released.get() {
+            assert!(Instant::now() < deadline, "fingerprint reader was never released");
+            conn.process(Duration::from_millis(50)).unwrap();
+        }
+    });
+    ready.recv().expect("mock fprintd failed to start");
+    mock
+}
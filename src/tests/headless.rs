@@ -0,0 +1,62 @@
+use std::fs::{self, File};
+
+use crate::{headless, renderer::{self, Renderer}};
+
+/// Solid blue, so every other pixel belongs to the overlay.
+const SHADER: &str = "
+void mainImage( out vec4 fragColor, in vec2 fragCoord )
+{
+    fragColor = vec4(0., 0., 1., 1.);
+}
+";
+
+const BLUE: [u8; 3] = [0, 0, 255];
+
+#[test]
+fn render_draws_shader_and_controls() {
+    if let Err(e) = Renderer::headless(1, 1, renderer::DEFAULT_SHADER) {
+        eprintln!("skipping, {}", e);
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let shader = dir.path().join("blue.glsl");
+    fs::write(&shader, SHADER).unwrap();
+    let output = dir.path().join("lock.png");
+
+    let args = [
+        format!("--output={}", output.display()),
+        "--size=320x240".to_string(),
+        format!("--shader={}", shader.display()),
+    ];
+    headless::run(args.to_vec()).unwrap();
+
+    let mut reader = png::Decoder::new(File::open(&output).unwrap()).read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    assert_eq!((info.width, info.height, info.color_type), (320, 240, png::ColorType::Rgb));
+    let rows: Vec<_> = pixels.chunks(info.line_size).collect();
+    let pixel = |x: usize, y: usize| [rows[y][x * 3], rows[y][x * 3 + 1], rows[y][x * 3 + 2]];
+
+    // the shader fills the corners, the overlay is centered
+    assert_eq!(pixel(0, 0), BLUE);
+    assert_eq!(pixel(319, 0), BLUE);
+    assert_eq!(pixel(0, 239), BLUE);
+    assert_eq!(pixel(319, 239), BLUE);
+    // without a message or failed attempts, only the password field and
+    // buttons can cover the middle
+    let covered = (80..240).any(|x| (60..180).any(|y| pixel(x, y) != BLUE));
+    assert!(covered, "password field and buttons were not drawn");
+}
+
+#[test]
+fn render_rejects_bad_options() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = format!("--output={}", dir.path().join("lock.png").display());
+
+    let error = headless::run(vec![output.clone(), "--no-such-option".to_string()]).unwrap_err();
+    assert!(error.contains("unknown option 'no-such-option'") && error.contains("usage:"), "{}", error);
+
+    let missing = format!("--config={}", dir.path().join("config").display());
+    assert!(headless::run(vec![output, missing]).is_err());
+    assert!(!dir.path().join("lock.png").exists());
+}
@@ -0,0 +1,89 @@
+use xkbcommon::xkb;
+
+use crate::{
+    config::Config,
+    keys::{self, Binding, Modifiers},
+    widget::{Action, MediaKey},
+};
+
+const CTRL: Modifiers = Modifiers { ctrl: true, alt: false, shift: false, logo: false };
+const NONE: Modifiers = Modifiers { ctrl: false, alt: false, shift: false, logo: false };
+
+/// xkb keycodes of the U and W keys, evdev ones plus 8.
+const KEY_U: xkb::Keycode = 22 + 8;
+const KEY_W: xkb::Keycode = 17 + 8;
+
+/// State of a us,ru keymap with the named modifiers held or locked.
+fn state(keymap: &xkb::Keymap, held: &[&str], locked: &[&str], layout: u32) -> xkb::State {
+    let mask = |names: &[&str]| names.iter().fold(0, |mask, name| mask | 1 << keymap.mod_get_index(name));
+    let mut state = xkb::State::new(keymap);
+    state.update_mask(mask(held), 0, mask(locked), 0, 0, layout);
+    state
+}
+
+#[test]
+fn most_specific_binding_wins() {
+    let bindings = keys::defaults();
+    assert_eq!(keys::lookup(&bindings, xkb::KEY_BackSpace, NONE), Some(Action::Backspace));
+    assert_eq!(keys::lookup(&bindings, xkb::KEY_BackSpace, CTRL), Some(Action::DeleteWord));
+    assert_eq!(keys::lookup(&bindings, xkb::KEY_u, CTRL), Some(Action::Clear));
+    // typed as usual without Ctrl
+    assert_eq!(keys::lookup(&bindings, xkb::KEY_u, NONE), None);
+    // extra modifiers do not get in the way
+    let shift = Modifiers { shift: true, ..CTRL };
+    assert_eq!(keys::lookup(&bindings, xkb::KEY_Return, shift), Some(Action::Submit));
+}
+
+#[test]
+fn lookup_ignores_caps_lock_and_layout() {
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = xkb::Keymap::new_from_names(&context, "evdev", "pc105", "us,ru", "", None, xkb::KEYMAP_COMPILE_NO_FLAGS)
+        .expect("unable to compile keymap");
+    let mut bindings = keys::defaults();
+    keys::add(&mut bindings, Binding::parse("Shift+W toggle-help").unwrap());
+    let lookup = |state: &xkb::State, keycode| keys::lookup_key(&bindings, &keymap, state, keycode);
+
+    let caps = state(&keymap, &[xkb::MOD_NAME_CTRL], &[xkb::MOD_NAME_CAPS], 0);
+    assert_eq!(caps.key_get_one_sym(KEY_U), xkb::KEY_U);
+    assert_eq!(lookup(&caps, KEY_U), Some(Action::Clear));
+    // typed as usual
+    assert_eq!(lookup(&state(&keymap, &[], &[xkb::MOD_NAME_CAPS], 0), KEY_U), None);
+
+    assert_eq!(lookup(&state(&keymap, &[xkb::MOD_NAME_SHIFT], &[], 0), KEY_W), Some(Action::ToggleHelp));
+
+    // Ctrl+w on the Cyrillic layout is still Ctrl+w, the letter alone is typed
+    assert_eq!(lookup(&state(&keymap, &[xkb::MOD_NAME_CTRL], &[], 1), KEY_W), Some(Action::DeleteWord));
+    assert_eq!(lookup(&state(&keymap, &[], &[], 1), KEY_W), None);
+}
+
+#[test]
+fn parse_binding() {
+    let binding = Binding::parse("Ctrl+Super+p play-pause").unwrap();
+    assert_eq!(binding.keysym, xkb::KEY_p);
+    assert_eq!(binding.modifiers, Modifiers { logo: true, ..CTRL });
+    assert_eq!(binding.action, Some(Action::Media(MediaKey::PlayPause)));
+    assert_eq!(binding.to_string(), "Ctrl+Super+p");
+
+    assert_eq!(Binding::parse("F2 none").unwrap().action, None);
+    assert!(Binding::parse("Ctrl+u").is_err());
+    assert!(Binding::parse("Hyper+u clear").is_err());
+    assert!(Binding::parse("Ctrl+nokey clear").is_err());
+    assert!(Binding::parse("Ctrl+u explode").is_err());
+}
+
+#[test]
+fn config_bindings_replace_defaults() {
+    let mut config = Config::default();
+    config.set("bind", "Ctrl+u none").unwrap();
+    config.set("bind", "F2 toggle-user").unwrap();
+    config.set("bind", "F3 fingerprint").unwrap();
+    config.set("bind", "Escape cycle-layout").unwrap();
+    assert!(config.set("bind", "F4").is_err());
+
+    let lookup = |keysym| keys::lookup(&config.bindings, keysym, NONE);
+    assert_eq!(keys::lookup(&config.bindings, xkb::KEY_u, CTRL), None);
+    assert_eq!(lookup(xkb::KEY_F2), Some(Action::ToggleUser));
+    assert_eq!(lookup(xkb::KEY_F3), Some(Action::Fingerprint));
+    assert_eq!(lookup(xkb::KEY_Escape), Some(Action::CycleLayout));
+    assert_eq!(config.bindings.iter().filter(|b| b.keysym == xkb::KEY_Escape).count(), 1);
+}
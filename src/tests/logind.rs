@@ -0,0 +1,111 @@
+use std::{
+    fs::File,
+    os::unix::io::IntoRawFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use dbus::{
+    arg::OwnedFd,
+    blocking::LocalConnection,
+    channel::{Channel, Sender},
+    message::MatchRule,
+    Message, Path,
+};
+
+use super::fprintd::Bus;
+
+const LOGIND: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_IFACE: &str = "org.freedesktop.login1.Manager";
+const SESSION_PATH: &str = "/org/freedesktop/login1/session/_31";
+const SESSION_IFACE: &str = "org.freedesktop.login1.Session";
+
+/// Mock logind with a single session, answering the calls wlock makes and
+/// sending the signals the tests ask for.
+pub struct Mock {
+    /// Calls in the order they arrived, `SetLockedHint` with its argument.
+    calls: mpsc::Receiver<String>,
+    signals: Channel,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Mock {
+    pub fn spawn(bus: &Bus) -> Self {
+        let address = bus.address.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (calls_tx, calls) = mpsc::channel();
+        let (ready_tx, ready) = mpsc::channel();
+        let s = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut channel = Channel::open_private(&address).unwrap();
+            channel.register().unwrap();
+            let conn = LocalConnection::from(channel);
+            conn.request_name(LOGIND, false, true, true).unwrap();
+
+            conn.start_receive(MatchRule::new_method_call(), Box::new(move |call, conn| {
+                let member = call.member().map(|m| m.to_string()).unwrap_or_default();
+                let reply = match member.as_str() {
+                    "GetSession" => call.method_return().append1(Path::from(SESSION_PATH)),
+                    "Inhibit" => {
+                        let file = File::open("/dev/null").unwrap();
+                        call.method_return().append1(unsafe { OwnedFd::new(file.into_raw_fd()) })
+                    }
+                    _ => call.method_return(),
+                };
+                conn.send(reply).unwrap();
+                let call = match member.as_str() {
+                    "SetLockedHint" => format!("{} {}", member, call.read1::<bool>().unwrap()),
+                    _ => member,
+                };
+                let _ = calls_tx.send(call);
+                true
+            }));
+            ready_tx.send(()).unwrap();
+
+            while !s.load(Ordering::Relaxed) {
+                conn.process(Duration::from_millis(50)).unwrap();
+            }
+        });
+        ready.recv().expect("mock logind failed to start");
+
+        let mut signals = Channel::open_private(&bus.address).unwrap();
+        signals.register().unwrap();
+        Mock { calls, signals, stop, thread: Some(thread) }
+    }
+
+    /// Whether wlock made the call `call` since the last time this returned
+    /// true. Calls before it are forgotten.
+    pub fn called(&self, call: &str) -> bool {
+        self.calls.try_iter().any(|c| c == call)
+    }
+
+    /// `loginctl unlock-session`
+    pub fn unlock(&self) {
+        self.signal(Message::new_signal(SESSION_PATH, SESSION_IFACE, "Unlock").unwrap());
+    }
+
+    /// The signal logind sends right before suspending.
+    pub fn prepare_for_sleep(&self) {
+        self.signal(Message::new_signal(MANAGER_PATH, MANAGER_IFACE, "PrepareForSleep").unwrap().append1(true));
+    }
+
+    fn signal(&self, signal: Message) {
+        self.signals.send(signal).unwrap();
+        self.signals.flush();
+    }
+}
+
+impl Drop for Mock {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,612 @@
+//! Runs the lock against a mock compositor, see `compositor`.
+
+mod auth;
+mod compositor;
+mod config;
+mod cursor;
+mod fprintd;
+mod headless;
+mod keys;
+mod logind;
+mod osk;
+mod password;
+mod state;
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use calloop::{channel, EventLoop};
+use wayland_client::Connection;
+use xkbcommon::xkb;
+
+use crate::{
+    app_data::AppData,
+    auth::Authenticator,
+    config::{AuthBackend, Config, EchoMode},
+    daemon,
+    fprint::Fprint,
+    logind::Logind,
+    renderer::LockRenderer,
+    security_key::{self, Outcome, SecurityKey},
+    session::{Error, Event, LockSession},
+    text::Item,
+};
+use compositor::{Compositor, LockReply, KEY_ENTER};
+
+/// Evdev keycode of the X key.
+const KEY_X: u32 = 45;
+
+/// The mock compositor has nothing EGL could draw with.
+struct NullRenderer((i32, i32));
+
+impl LockRenderer for NullRenderer {
+    fn set_overlay(&mut self, _: Vec<Vec<Item>>) {}
+
+    fn render(&self, _: u32) {}
+
+    fn size(&self) -> (i32, i32) {
+        self.0
+    }
+
+    fn resize(&mut self, width: i32, height: i32) {
+        self.0 = (width, height);
+    }
+}
+
+/// Reports the outcomes one check after another, then never finds a key.
+struct ScriptedKey(Vec<Outcome>);
+
+impl SecurityKey for ScriptedKey {
+    fn check(&mut self, _: &security_key::Cancel) -> Outcome {
+        if self.0.is_empty() {
+            return Outcome::NoKey;
+        }
+        self.0.remove(0)
+    }
+}
+
+/// Answers each check with what is sent on the channel, to catch the lock
+/// while a password is being checked.
+struct HeldAuth(mpsc::Receiver<bool>);
+
+impl Authenticator for HeldAuth {
+    fn check(&mut self, _: &str) -> bool {
+        self.0.recv().unwrap_or(false)
+    }
+}
+
+/// Accepts "x" as the password, typed with `KEY_X`.
+fn test_config(socket: &Path) -> Config {
+    Config {
+        auth: AuthBackend::Stub,
+        stub_password: "x".to_string(),
+        socket: Some(socket.to_path_buf()),
+        fail_delay: Duration::ZERO,
+        battery_static: false,
+        ..Config::default()
+    }
+}
+
+/// Lock against the mock compositor on another thread, with the control socket at `socket`.
+fn spawn_lock(client: UnixStream, socket: &Path) -> JoinHandle<Result<(), Error>> {
+    let config = test_config(socket);
+    thread::spawn(move || {
+        let conn = Connection::from_socket(client).expect("unable to connect to mock compositor");
+        LockSession::new(&conn, config)
+            .renderer(|_, _, size, _| Box::new(NullRenderer(size)))
+            .lock()
+    })
+}
+
+/// Send one control socket request and return the reply.
+fn request(socket: &Path, line: &str) -> String {
+    let mut stream = UnixStream::connect(socket).expect("unable to connect to control socket");
+    writeln!(stream, "{}", line).unwrap();
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply).unwrap();
+    reply.trim_end().to_string()
+}
+
+/// Unlock through the control socket and wait for the lock to end.
+fn quit(compositor: &mut Compositor, socket: &Path, lock: JoinHandle<Result<(), Error>>) {
+    assert_eq!(request(socket, "quit"), "ok");
+    compositor.run_until(|state| state.unlocked);
+    compositor.run_until(|_| lock.is_finished());
+    lock.join().expect("lock panicked").expect("lock failed");
+}
+
+#[test]
+fn lock_surface_for_every_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let (mut compositor, client) = Compositor::new(3, LockReply::Locked);
+    let lock = spawn_lock(client, &socket);
+
+    // bound once the lock is fully set up
+    compositor.run_until(|_| socket.exists());
+    let state = &compositor.state;
+    assert_eq!(state.outputs.len(), 3);
+    assert_eq!(state.lock_surfaces.len(), 3);
+    for output in &state.outputs {
+        assert_eq!(state.lock_surfaces.iter().filter(|(_, o)| o == output).count(), 1);
+    }
+
+    quit(&mut compositor, &socket, lock);
+}
+
+#[test]
+fn stays_locked_without_authentication() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let (mut compositor, client) = Compositor::new(1, LockReply::Locked);
+    let lock = spawn_lock(client, &socket);
+
+    compositor.run_until(|_| socket.exists());
+    compositor.focus();
+    compositor.key(KEY_ENTER);
+    compositor.run_for(Duration::from_millis(200));
+    assert!(!compositor.state.unlocked);
+    assert!(!compositor.state.lock_destroyed);
+
+    quit(&mut compositor, &socket, lock);
+}
+
+#[test]
+fn wrong_password_keeps_lock() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let (mut compositor, client) = Compositor::new(1, LockReply::Locked);
+    let lock = spawn_lock(client, &socket);
+
+    compositor.run_until(|_| socket.exists());
+    compositor.focus();
+    compositor.key(KEY_X);
+    compositor.key(KEY_X);
+    compositor.key(KEY_ENTER);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !request(&socket, "status").contains("\"failed_attempts\":1") {
+        assert!(Instant::now() < deadline, "attempt was never counted");
+        compositor.run_for(Duration::from_millis(50));
+    }
+    assert!(!compositor.state.unlocked);
+    assert!(!compositor.state.lock_destroyed);
+
+    quit(&mut compositor, &socket, lock);
+}
+
+/// Serve the compositor until the status reply contains `needle`.
+fn wait_for_status(compositor: &mut Compositor, socket: &Path, needle: &str) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !request(socket, "status").contains(needle) {
+        assert!(Instant::now() < deadline, "status never contained {}", needle);
+        compositor.run_for(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn status_follows_password_check() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let (mut compositor, client) = Compositor::new(1, LockReply::Locked);
+
+    let config = test_config(&socket);
+    let (release, held) = mpsc::channel();
+    let lock = thread::spawn(move || {
+        let conn = Connection::from_socket(client).expect("unable to connect to mock compositor");
+        LockSession::new(&conn, config)
+            .renderer(|_, _, size, _| Box::new(NullRenderer(size)))
+            .authenticator(HeldAuth(held))
+            .lock()
+    });
+
+    compositor.run_until(|_| socket.exists());
+    wait_for_status(&mut compositor, &socket, "\"state\":\"locked\"");
+    compositor.focus();
+    compositor.key(KEY_X);
+    compositor.key(KEY_ENTER);
+
+    // the event loop keeps answering while the check is running
+    wait_for_status(&mut compositor, &socket, "\"state\":\"authenticating\"");
+    release.send(false).unwrap();
+    wait_for_status(&mut compositor, &socket, "\"state\":\"locked\",\"failed_attempts\":1");
+    assert!(!compositor.state.unlocked);
+
+    quit(&mut compositor, &socket, lock);
+}
+
+#[test]
+fn correct_password_unlocks() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let (mut compositor, client) = Compositor::new(1, LockReply::Locked);
+    let lock = spawn_lock(client, &socket);
+
+    compositor.run_until(|_| socket.exists());
+    compositor.focus();
+    compositor.key(KEY_X);
+    compositor.key(KEY_ENTER);
+    compositor.run_until(|state| state.unlocked);
+    compositor.run_until(|_| lock.is_finished());
+    lock.join().expect("lock panicked").expect("lock failed");
+}
+
+#[test]
+fn finished_lock_is_fatal() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let (mut compositor, client) = Compositor::new(1, LockReply::Finished);
+    let lock = spawn_lock(client, &socket);
+
+    compositor.run_until(|_| lock.is_finished());
+    assert!(matches!(lock.join().expect("lock panicked"), Err(Error::LockFinished)));
+    assert!(!compositor.state.unlocked);
+    assert!(!socket.exists());
+}
+
+#[test]
+fn unusable_shadow_file_refuses_to_lock() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let (mut compositor, client) = Compositor::new(1, LockReply::Locked);
+
+    let config = Config {
+        auth: AuthBackend::Shadow,
+        shadow_file: dir.path().join("shadow"),
+        ..test_config(&socket)
+    };
+    let lock = thread::spawn(move || {
+        let conn = Connection::from_socket(client).expect("unable to connect to mock compositor");
+        LockSession::new(&conn, config)
+            .renderer(|_, _, size, _| Box::new(NullRenderer(size)))
+            .lock()
+    });
+
+    compositor.run_until(|_| lock.is_finished());
+    assert!(matches!(lock.join().expect("lock panicked"), Err(Error::Auth(_))));
+    assert!(compositor.state.lock.is_none());
+}
+
+#[test]
+fn unlock_handle_ends_lock() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let (mut compositor, client) = Compositor::new(1, LockReply::Locked);
+
+    let config = test_config(&socket);
+    let (handles, handle) = mpsc::channel();
+    let (events_tx, events) = mpsc::channel();
+    let lock = thread::spawn(move || {
+        let conn = Connection::from_socket(client).expect("unable to connect to mock compositor");
+        let mut session = LockSession::new(&conn, config)
+            .renderer(|_, _, size, _| Box::new(NullRenderer(size)))
+            .on_event(move |event| {
+                let name = match event {
+                    Event::Locked => "locked",
+                    Event::AttemptFailed { .. } => "failed",
+                    Event::Unlocked { .. } => "unlocked",
+                };
+                events_tx.send(name).unwrap();
+            });
+        handles.send(session.unlock_handle().unwrap()).unwrap();
+        session.lock()
+    });
+
+    compositor.run_until(|_| socket.exists());
+    assert!(!compositor.state.unlocked);
+    handle.recv().unwrap().unlock();
+    compositor.run_until(|state| state.unlocked);
+    compositor.run_until(|_| lock.is_finished());
+    lock.join().expect("lock panicked").expect("lock failed");
+
+    assert_eq!(events.try_iter().collect::<Vec<_>>(), ["locked", "unlocked"]);
+}
+
+/// Lock with the fingerprint reader served by `fprintd` on `bus`.
+fn spawn_fingerprint_lock(client: UnixStream, socket: &Path, bus: &fprintd::Bus) -> JoinHandle<Result<(), Error>> {
+    let config = test_config(socket);
+    let address = bus.address.clone();
+    thread::spawn(move || {
+        let conn = Connection::from_socket(client).expect("unable to connect to mock compositor");
+        LockSession::new(&conn, config)
+            .renderer(|_, _, size, _| Box::new(NullRenderer(size)))
+            .fingerprint(move || Fprint::open(&address))
+            .lock()
+    })
+}
+
+#[test]
+fn fingerprint_unlocks() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let bus = match fprintd::Bus::new() {
+        Some(bus) => bus,
+        None => return,
+    };
+    let fprintd = fprintd::spawn(&bus, &["verify-match"]);
+    let (mut compositor, client) = Compositor::new(1, LockReply::Locked);
+    let lock = spawn_fingerprint_lock(client, &socket, &bus);
+
+    compositor.run_until(|state| state.unlocked);
+    compositor.run_until(|_| lock.is_finished());
+    lock.join().expect("lock panicked").expect("lock failed");
+    fprintd.join().expect("mock fprintd panicked");
+}
+
+#[test]
+fn unknown_finger_keeps_lock() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let bus = match fprintd::Bus::new() {
+        Some(bus) => bus,
+        None => return,
+    };
+    let fprintd = fprintd::spawn(&bus, &["verify-no-match"]);
+    let (mut compositor, client) = Compositor::new(1, LockReply::Locked);
+    let lock = spawn_fingerprint_lock(client, &socket, &bus);
+
+    compositor.run_until(|_| socket.exists());
+    compositor.run_for(Duration::from_millis(500));
+    assert!(!compositor.state.unlocked);
+    // an unknown finger is no failed attempt
+    assert!(request(&socket, "status").contains("\"failed_attempts\":0"));
+
+    // the password keeps working while the reader waits for another finger
+    compositor.focus();
+    compositor.key(KEY_X);
+    compositor.key(KEY_ENTER);
+    compositor.run_until(|state| state.unlocked);
+    compositor.run_until(|_| lock.is_finished());
+    lock.join().expect("lock panicked").expect("lock failed");
+    fprintd.join().expect("mock fprintd panicked");
+}
+
+#[test]
+fn second_finger_unlocks() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let bus = match fprintd::Bus::new() {
+        Some(bus) => bus,
+        None => return,
+    };
+    let fprintd = fprintd::spawn(&bus, &["verify-no-match", "verify-match"]);
+    let (mut compositor, client) = Compositor::new(1, LockReply::Locked);
+    let lock = spawn_fingerprint_lock(client, &socket, &bus);
+
+    compositor.run_until(|state| state.unlocked);
+    compositor.run_until(|_| lock.is_finished());
+    lock.join().expect("lock panicked").expect("lock failed");
+    fprintd.join().expect("mock fprintd panicked");
+}
+
+#[test]
+fn logind_unlock_ends_lock() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let bus = match fprintd::Bus::new() {
+        Some(bus) => bus,
+        None => return,
+    };
+    let logind = logind::Mock::spawn(&bus);
+    let (mut compositor, client) = Compositor::new(1, LockReply::Locked);
+
+    let config = test_config(&socket);
+    let address = bus.address.clone();
+    let lock = thread::spawn(move || {
+        let conn = Connection::from_socket(client).expect("unable to connect to mock compositor");
+        LockSession::new(&conn, config)
+            .renderer(|_, _, size, _| Box::new(NullRenderer(size)))
+            .logind(Some(Logind::open(&address)?), false)
+            .lock()
+    });
+
+    compositor.run_until(|_| logind.called("SetLockedHint true"));
+    assert!(!compositor.state.unlocked);
+    logind.unlock();
+    compositor.run_until(|state| state.unlocked);
+    compositor.run_until(|_| lock.is_finished());
+    lock.join().expect("lock panicked").expect("lock failed");
+    assert!(logind.called("SetLockedHint false"));
+}
+
+#[test]
+fn daemon_locks_before_sleep() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let bus = match fprintd::Bus::new() {
+        Some(bus) => bus,
+        None => return,
+    };
+    let logind = logind::Mock::spawn(&bus);
+    let (mut compositor, client) = Compositor::new(1, LockReply::Locked);
+
+    // only logind locks, the mock compositor has no idle notifier
+    let config = Config { idle_lock: Duration::ZERO, ..test_config(&socket) };
+    let address = bus.address.clone();
+    let daemon = thread::spawn(move || {
+        let conn = Connection::from_socket(client).expect("unable to connect to mock compositor");
+        let logind = Logind::open(&address)?;
+        daemon::run_with(&conn, &config, Some(logind), |session| {
+            session.renderer(|_, _, size, _| Box::new(NullRenderer(size)))
+        })
+    });
+
+    compositor.run_until(|_| logind.called("Inhibit"));
+    assert!(compositor.state.lock.is_none());
+    logind.prepare_for_sleep();
+    compositor.run_until(|_| logind.called("SetLockedHint true"));
+    assert!(compositor.state.lock.is_some());
+
+    // back to waiting with a new inhibitor
+    compositor.run_until(|_| socket.exists());
+    assert_eq!(request(&socket, "quit"), "ok");
+    compositor.run_until(|state| state.unlocked);
+    compositor.run_until(|_| logind.called("Inhibit"));
+
+    // a lock that fails is no reason to leave the next sleep unlocked
+    compositor.state.reply = LockReply::Finished;
+    logind.prepare_for_sleep();
+    compositor.run_for(Duration::from_millis(500));
+    assert!(!daemon.is_finished());
+    compositor.state.reply = LockReply::Locked;
+    compositor.state.unlocked = false;
+    logind.prepare_for_sleep();
+    compositor.run_until(|_| logind.called("SetLockedHint true"));
+    compositor.run_until(|_| socket.exists());
+    assert_eq!(request(&socket, "quit"), "ok");
+    compositor.run_until(|state| state.unlocked);
+
+    // only losing the compositor ends the daemon
+    drop(compositor);
+    let result = daemon.join().expect("daemon panicked");
+    assert!(matches!(result, Err(Error::Wayland(_) | Error::EventLoop(_))));
+}
+
+#[test]
+fn security_key_unlocks() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("wlock.sock");
+    let (mut compositor, client) = Compositor::new(1, LockReply::Locked);
+
+    let config = test_config(&socket);
+    let lock = thread::spawn(move || {
+        let conn = Connection::from_socket(client).expect("unable to connect to mock compositor");
+        LockSession::new(&conn, config)
+            .renderer(|_, _, size, _| Box::new(NullRenderer(size)))
+            .security_key(ScriptedKey(vec![Outcome::Touched]))
+            .lock()
+    });
+
+    compositor.run_until(|state| state.unlocked);
+    compositor.run_until(|_| lock.is_finished());
+    lock.join().expect("lock panicked").expect("lock failed");
+}
+
+/// Whether the overlay shows `text` as a label.
+fn shown(app_data: &mut AppData, text: &str) -> bool {
+    app_data.overlay_rows().iter().flatten().any(|item| *item == Item::Text(text.to_string()))
+}
+
+#[test]
+fn missing_security_key_waits_for_retry() {
+    let (handle, channel) = security_key::spawn(Box::new(ScriptedKey(vec![Outcome::NoKey, Outcome::Touched])));
+    let mut event_loop: EventLoop<Vec<security_key::Event>> = EventLoop::try_new().unwrap();
+    event_loop.handle().insert_source(channel, |event, _, events| {
+        if let channel::Event::Msg(event) = event {
+            events.push(event);
+        }
+    }).unwrap();
+
+    // longer than a timed out check waits before the next one
+    let mut events = vec![];
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while Instant::now() < deadline {
+        event_loop.dispatch(Duration::from_millis(100), &mut events).unwrap();
+    }
+    assert_eq!(events, [security_key::Event::Waiting, security_key::Event::Done(Outcome::NoKey)]);
+
+    handle.retry();
+    events.clear();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while events.len() < 2 {
+        assert!(Instant::now() < deadline, "retry never checked again");
+        event_loop.dispatch(Duration::from_millis(100), &mut events).unwrap();
+    }
+    assert_eq!(events, [security_key::Event::Waiting, security_key::Event::Done(Outcome::Touched)]);
+}
+
+#[test]
+fn security_key_errors_are_shown() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut app_data = AppData::new(test_config(&dir.path().join("wlock.sock")), None, false);
+
+    app_data.handle_security_key(security_key::Event::Waiting);
+    assert!(shown(&mut app_data, "Touch your security key"));
+    app_data.handle_security_key(security_key::Event::Done(Outcome::NoKey));
+    assert!(shown(&mut app_data, "No security key found"));
+    app_data.handle_security_key(security_key::Event::Done(Outcome::Timeout));
+    assert!(shown(&mut app_data, "Security key was not touched in time"));
+    assert!(!shown(&mut app_data, "No security key found"));
+}
+
+#[test]
+fn keyboard_indicators() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut app_data = AppData::new(test_config(&dir.path().join("wlock.sock")), None, false);
+    let keymap = xkb::Keymap::new_from_names(&app_data.xkb_context, "evdev", "pc105", "us,de", "", None, xkb::KEYMAP_COMPILE_NO_FLAGS)
+        .expect("unable to compile keymap");
+    let caps = 1 << keymap.mod_get_index(xkb::MOD_NAME_CAPS);
+    app_data.xkb_state = Some(xkb::State::new(&keymap));
+    app_data.xkb_keymap = Some(keymap);
+    let layout_shown = |app_data: &mut AppData, name: &str| {
+        app_data.overlay_rows().iter().flatten().any(|item| *item == Item::Button(name.to_string()))
+    };
+
+    assert!(!shown(&mut app_data, "Caps Lock"));
+    assert!(layout_shown(&mut app_data, "English (US)"));
+
+    app_data.set_modifiers([0, 0, caps, 0]);
+    assert!(shown(&mut app_data, "Caps Lock"));
+
+    app_data.cycle_layout();
+    assert!(layout_shown(&mut app_data, "German"));
+    assert!(shown(&mut app_data, "Caps Lock"));
+    app_data.cycle_layout();
+    assert!(layout_shown(&mut app_data, "English (US)"));
+
+    // switching in the compositor still works on top
+    app_data.set_modifiers([0, 0, 0, 1]);
+    assert!(layout_shown(&mut app_data, "German"));
+    assert!(!shown(&mut app_data, "Caps Lock"));
+}
+
+#[test]
+fn password_echo_modes() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut app_data = AppData::new(test_config(&dir.path().join("wlock.sock")), None, false);
+    app_data.password.insert("hunter2");
+
+    app_data.config.echo = EchoMode::None;
+    assert_eq!(app_data.password_echo(), "");
+
+    app_data.config.echo = EchoMode::Bullets;
+    assert_eq!(app_data.password_echo(), "\u{2022}".repeat(7));
+
+    // the count must not follow the length
+    app_data.config.echo = EchoMode::Random;
+    let counts: Vec<_> = (1..=20)
+        .map(|len| {
+            app_data.password.clear();
+            app_data.password.insert(&"x".repeat(len));
+            app_data.password_echo().chars().count()
+        })
+        .collect();
+    assert!(counts.iter().all(|&n| (4..13).contains(&n)));
+    assert!(counts.windows(2).any(|w| w[1] < w[0]));
+    assert!(app_data.password_echo().chars().all(|c| c == '\u{2022}'));
+    // nor the content, retyping the same password draws a new count
+    let counts: Vec<_> = (0..20)
+        .map(|_| {
+            app_data.password.clear();
+            app_data.password.insert("hunter2");
+            app_data.password_echo().chars().count()
+        })
+        .collect();
+    assert!(counts.windows(2).any(|w| w[1] != w[0]));
+
+    app_data.config.echo = EchoMode::Reveal;
+    app_data.password.clear();
+    app_data.password.insert("hunter2");
+    assert_eq!(app_data.password_echo(), "\u{2022}".repeat(7));
+    app_data.revealed = true;
+    assert_eq!(app_data.password_echo(), "hunter2|");
+    app_data.password.left();
+    app_data.password.left();
+    assert_eq!(app_data.password_echo(), "hunte|r2");
+}
@@ -0,0 +1,13 @@
+use crate::osk::us_key_text;
+
+#[test]
+fn us_labels_without_keymap() {
+    assert_eq!(us_key_text(2, 0), "1");
+    assert_eq!(us_key_text(2, 1), "!");
+    assert_eq!(us_key_text(16, 0), "q");
+    assert_eq!(us_key_text(40, 1), "\"");
+    assert_eq!(us_key_text(53, 0), "/");
+    assert_eq!(us_key_text(57, 0), " ");
+    // not on the on-screen keyboard
+    assert_eq!(us_key_text(1, 0), "");
+}
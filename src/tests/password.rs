@@ -0,0 +1,60 @@
+use crate::password::Password;
+
+fn password(text: &str) -> Password {
+    let mut password = Password::default();
+    password.insert(text);
+    password
+}
+
+#[test]
+fn insert_at_cursor() {
+    let mut p = password("hunter");
+    p.home();
+    p.insert("ä");
+    p.end();
+    p.insert("2");
+    p.left();
+    p.left();
+    p.insert("-");
+    assert_eq!(p.as_str(), "ähunte-r2");
+    assert_eq!(p.cursor(), 7);
+    assert_eq!(p.len(), 9);
+}
+
+#[test]
+fn delete_around_cursor() {
+    let mut p = password("aöb");
+    p.left();
+    p.backspace();
+    assert_eq!(p.as_str(), "ab");
+    p.delete();
+    assert_eq!(p.as_str(), "a");
+    // nothing under the cursor
+    p.delete();
+    assert_eq!(p.as_str(), "a");
+    p.home();
+    p.backspace();
+    p.left();
+    assert_eq!((p.as_str(), p.cursor()), ("a", 0));
+}
+
+#[test]
+fn delete_word() {
+    let mut p = password("correct horse  battery");
+    p.delete_word();
+    assert_eq!(p.as_str(), "correct horse  ");
+    p.delete_word();
+    assert_eq!(p.as_str(), "correct ");
+    p.insert("staple");
+    p.left();
+    p.left();
+    p.delete_word();
+    assert_eq!((p.as_str(), p.cursor()), ("correct le", 8));
+    p.home();
+    p.delete_word();
+    assert_eq!(p.as_str(), "correct le");
+    p.end();
+    p.delete_word();
+    p.delete_word();
+    assert!(p.is_empty());
+}
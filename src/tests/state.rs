@@ -0,0 +1,78 @@
+use crate::state::{Lifecycle, LockState, Unlock, UnlockReason};
+
+/// Drive a new lifecycle through `states`, all of which have to be allowed.
+fn lifecycle(states: &[LockState]) -> Lifecycle {
+    let mut lifecycle = Lifecycle::new();
+    for &state in states {
+        lifecycle.transition(state).unwrap();
+    }
+    lifecycle
+}
+
+#[test]
+fn password_unlock() {
+    let mut lifecycle = lifecycle(&[
+        LockState::LockRequested,
+        LockState::Locked,
+        LockState::Authenticating,
+        LockState::Locked,
+        LockState::Authenticating,
+    ]);
+    lifecycle.unlock(Unlock::new(UnlockReason::Password)).unwrap();
+    lifecycle.transition(LockState::Done).unwrap();
+    assert_eq!(lifecycle.get(), LockState::Done);
+}
+
+#[test]
+fn unlock_needs_authentication() {
+    let mut lifecycle = lifecycle(&[LockState::LockRequested, LockState::Locked]);
+    assert!(lifecycle.transition(LockState::Unlocking).is_err());
+    assert!(lifecycle.unlock(Unlock::new(UnlockReason::Password)).is_err());
+    assert!(lifecycle.transition(LockState::Done).is_err());
+    assert_eq!(lifecycle.get(), LockState::Locked);
+
+    // being in Authenticating is not enough without the password checking out
+    lifecycle.transition(LockState::Authenticating).unwrap();
+    assert!(lifecycle.transition(LockState::Unlocking).is_err());
+    assert_eq!(lifecycle.get(), LockState::Authenticating);
+}
+
+#[test]
+fn trusted_unlock() {
+    let mut lifecycle = lifecycle(&[LockState::LockRequested, LockState::Locked]);
+    lifecycle.unlock(Unlock::new(UnlockReason::Trusted)).unwrap();
+    assert_eq!(lifecycle.get(), LockState::Unlocking);
+}
+
+#[test]
+fn no_unlock_before_locked() {
+    let mut lifecycle = lifecycle(&[LockState::LockRequested]);
+    assert!(lifecycle.transition(LockState::Authenticating).is_err());
+    assert!(lifecycle.unlock(Unlock::new(UnlockReason::Trusted)).is_err());
+    assert_eq!(lifecycle.get(), LockState::LockRequested);
+}
+
+#[test]
+fn no_lock_without_request() {
+    let mut lifecycle = Lifecycle::new();
+    assert!(lifecycle.transition(LockState::Locked).is_err());
+    assert_eq!(lifecycle.get(), LockState::Connecting);
+}
+
+#[test]
+fn failed_is_final() {
+    let mut lifecycle = lifecycle(&[LockState::LockRequested, LockState::Failed]);
+    for state in [LockState::Locked, LockState::Authenticating, LockState::Unlocking, LockState::Done] {
+        assert!(lifecycle.transition(state).is_err());
+    }
+    assert_eq!(lifecycle.get(), LockState::Failed);
+}
+
+#[test]
+fn done_is_final() {
+    let mut lifecycle = lifecycle(&[LockState::LockRequested, LockState::Locked]);
+    lifecycle.unlock(Unlock::new(UnlockReason::OtherFactor)).unwrap();
+    lifecycle.transition(LockState::Done).unwrap();
+    assert!(lifecycle.transition(LockState::Locked).is_err());
+    assert!(lifecycle.transition(LockState::Failed).is_err());
+}
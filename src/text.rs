@@ -0,0 +1,165 @@
+use fontdue::{Font, FontSettings};
+
+/// Single channel coverage bitmap, rows top to bottom.
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+/// Area of an item inside the bitmap, in pixels.
+#[derive(Clone, Copy, Default)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x as f64 && x < (self.x + self.width) as f64 &&
+            y >= self.y as f64 && y < (self.y + self.height) as f64
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Item {
+    Text(String),
+    /// Text in an outlined box.
+    Field(String),
+    /// Text on a filled box.
+    Button(String),
+}
+
+impl Item {
+    fn text(&self) -> &str {
+        match self {
+            Item::Text(t) | Item::Field(t) | Item::Button(t) => t,
+        }
+    }
+}
+
+const BOX_ALPHA: u8 = 64;
+
+pub struct TextRasterizer {
+    font: Font,
+    px: f32,
+}
+
+impl TextRasterizer {
+    pub fn new(px: f32) -> Self {
+        let font = Font::from_bytes(notosans::REGULAR_TTF, FontSettings::default())
+            .expect("unable to load embedded font");
+        TextRasterizer { font, px }
+    }
+
+    /// Lay out `rows` centered below each other, items in a row side by side.
+    /// Returns the bitmap and the area of every item, row by row.
+    pub fn rasterize(&self, rows: &[Vec<Item>]) -> (Bitmap, Vec<Vec<Rect>>) {
+        let metrics = self.font.horizontal_line_metrics(self.px)
+            .expect("font has no horizontal metrics");
+        let line_height = metrics.new_line_size.ceil() as usize;
+        let pad = line_height / 4;
+
+        let mut rects: Vec<Vec<Rect>> = vec![];
+        let mut y = 0;
+        let mut width = 0;
+        for row in rows {
+            let boxed = row.iter().any(|i| !matches!(i, Item::Text(_)));
+            let height = if boxed { line_height + 2 * pad } else { line_height };
+            let mut x = 0;
+            let mut row_rects = vec![];
+            for item in row {
+                if x > 0 {
+                    x += pad;
+                }
+                let mut w = self.advance(item.text()).ceil() as usize;
+                if !matches!(item, Item::Text(_)) {
+                    // at least square, so single character buttons line up
+                    w = (w + 2 * pad).max(height);
+                }
+                row_rects.push(Rect { x, y, width: w, height });
+                x += w;
+            }
+            width = width.max(x);
+            y += height + pad;
+            rects.push(row_rects);
+        }
+        let height = y.saturating_sub(pad);
+
+        // center every row
+        for row in &mut rects {
+            let row_width = row.last().map_or(0, |r| r.x + r.width);
+            for r in row.iter_mut() {
+                r.x += (width - row_width) / 2;
+            }
+        }
+
+        let mut bitmap = Bitmap { width, height, data: vec![0; width * height] };
+
+        for (row, row_rects) in rows.iter().zip(&rects) {
+            for (item, rect) in row.iter().zip(row_rects) {
+                match item {
+                    Item::Text(_) => {}
+                    Item::Field(_) => outline(&mut bitmap, rect),
+                    Item::Button(_) => fill(&mut bitmap, rect, BOX_ALPHA),
+                }
+                let text = item.text();
+                let left = rect.x as f32 + (rect.width as f32 - self.advance(text)) / 2.;
+                let baseline = rect.y as f32 + (rect.height - line_height) as f32 / 2. + metrics.ascent;
+                self.draw(&mut bitmap, text, left, baseline);
+            }
+        }
+
+        (bitmap, rects)
+    }
+
+    fn advance(&self, text: &str) -> f32 {
+        text.chars().map(|c| self.font.metrics(c, self.px).advance_width).sum()
+    }
+
+    fn draw(&self, bitmap: &mut Bitmap, text: &str, mut x: f32, baseline: f32) {
+        for c in text.chars() {
+            let (m, glyph) = self.font.rasterize(c, self.px);
+            let left = (x + m.xmin as f32).round() as isize;
+            let top = (baseline - (m.ymin as f32 + m.height as f32)).round() as isize;
+            blit(bitmap, &glyph, m.width, m.height, left, top);
+            x += m.advance_width;
+        }
+    }
+}
+
+fn fill(bitmap: &mut Bitmap, rect: &Rect, alpha: u8) {
+    for y in rect.y..(rect.y + rect.height).min(bitmap.height) {
+        for x in rect.x..(rect.x + rect.width).min(bitmap.width) {
+            let dst = &mut bitmap.data[y * bitmap.width + x];
+            *dst = (*dst).max(alpha);
+        }
+    }
+}
+
+fn outline(bitmap: &mut Bitmap, rect: &Rect) {
+    let (x, y, w, h) = (rect.x, rect.y, rect.width, rect.height);
+    fill(bitmap, &Rect { x, y, width: w, height: 2 }, 255);
+    fill(bitmap, &Rect { x, y: (y + h).saturating_sub(2), width: w, height: 2 }, 255);
+    fill(bitmap, &Rect { x, y, width: 2, height: h }, 255);
+    fill(bitmap, &Rect { x: (x + w).saturating_sub(2), y, width: 2, height: h }, 255);
+}
+
+fn blit(bitmap: &mut Bitmap, glyph: &[u8], w: usize, h: usize, left: isize, top: isize) {
+    for gy in 0..h {
+        let y = top + gy as isize;
+        if y < 0 || y >= bitmap.height as isize {
+            continue;
+        }
+        for gx in 0..w {
+            let x = left + gx as isize;
+            if x < 0 || x >= bitmap.width as isize {
+                continue;
+            }
+            let dst = &mut bitmap.data[y as usize * bitmap.width + x as usize];
+            *dst = (*dst).max(glyph[gy * w + gx]);
+        }
+    }
+}
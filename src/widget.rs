@@ -0,0 +1,66 @@
+/// What happens when a widget is clicked or tapped, or a bound key is pressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Submit,
+    Clear,
+    Backspace,
+    /// Delete the word before the cursor.
+    DeleteWord,
+    /// Delete the character under the cursor.
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    /// Type the character of an evdev keycode, from the on-screen keyboard.
+    Key(u32),
+    Shift,
+    ToggleKeyboard,
+    /// Switch to the next keyboard layout of the keymap.
+    CycleLayout,
+    /// Show or hide the password in the `reveal` echo mode.
+    ToggleReveal,
+    /// Show or hide who the session belongs to.
+    ToggleUser,
+    /// Show or hide the list of key bindings.
+    ToggleHelp,
+    /// Start a new fingerprint scan.
+    Fingerprint,
+    /// Look for a security key again after none was found.
+    SecurityKey,
+    Media(MediaKey),
+}
+
+impl Action {
+    /// Whether holding a key bound to the action repeats it. Only editing
+    /// does, toggling something on every repeat is never wanted.
+    pub fn repeats(self) -> bool {
+        matches!(self, Action::Backspace | Action::DeleteWord | Action::Delete | Action::Left | Action::Right)
+    }
+}
+
+/// Media player controls, sent to the first MPRIS player on the session bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaKey {
+    PlayPause,
+    Next,
+    Previous,
+}
+
+/// Elements of the lock screen overlay, laid out in rows.
+pub enum Widget {
+    Label(String),
+    /// Tapping it toggles the on-screen keyboard.
+    Password,
+    Button(String, Action),
+}
+
+impl Widget {
+    pub fn action(&self) -> Option<Action> {
+        match self {
+            Widget::Password => Some(Action::ToggleKeyboard),
+            Widget::Button(_, action) => Some(*action),
+            _ => None,
+        }
+    }
+}
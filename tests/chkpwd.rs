@@ -0,0 +1,48 @@
+//! `auth::Shadow` through the real `wlock-chkpwd`, run unprivileged on a
+//! shadow file of its own.
+
+use std::{fs, path::Path};
+
+use wlock::{Authenticator, Shadow};
+
+fn shadow(file: &Path) -> Shadow {
+    Shadow::new(file).helper(Path::new(env!("CARGO_BIN_EXE_wlock-chkpwd")))
+}
+
+/// A shadow file with `hash` for the current user.
+fn shadow_file(hash: &str) -> tempfile::NamedTempFile {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let user = users::get_current_username().unwrap().to_string_lossy().into_owned();
+    fs::write(file.path(), format!("{}:{}:19000:0:99999:7:::\n", user, hash)).unwrap();
+    file
+}
+
+#[test]
+fn helper_checks_password() {
+    let file = shadow_file(&pwhash::sha512_crypt::hash("hunter2").unwrap());
+    let mut shadow = shadow(file.path());
+    assert!(shadow.ready().is_ok());
+    assert!(shadow.check("hunter2"));
+    assert!(!shadow.check("hunter3"));
+    assert!(!shadow.check(""));
+}
+
+#[test]
+fn helper_reports_unusable_hash() {
+    let file = shadow_file("!");
+    let mut shadow = shadow(file.path());
+    let error = shadow.ready().unwrap_err();
+    assert!(error.contains("locked"), "{}", error);
+    assert!(!shadow.check(""));
+
+    let dir = tempfile::tempdir().unwrap();
+    assert!(shadow(&dir.path().join("shadow")).ready().is_err());
+}
+
+#[test]
+fn missing_helper_is_an_error() {
+    let file = shadow_file(&pwhash::sha512_crypt::hash("hunter2").unwrap());
+    let mut shadow = Shadow::new(file.path()).helper(Path::new("/nonexistent/wlock-chkpwd"));
+    assert!(shadow.ready().is_err());
+    assert!(!shadow.check("hunter2"));
+}